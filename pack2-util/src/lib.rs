@@ -1,9 +1,23 @@
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
+use bstr::{io::BufReadExt, ByteSlice};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
 use faster_hex::{hex_decode, hex_encode};
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use memmap2::Mmap;
+use xz2::bufread::XzDecoder;
+use xz2::write::XzEncoder;
+
+pub mod lzma2;
+pub mod mask;
+pub mod spacesaving;
+pub mod utf8;
 
 #[inline(always)]
 pub fn contains_uppercase(line: &[u8]) -> bool {
@@ -33,7 +47,7 @@ pub fn encode_hex_if_needed(line: Vec<u8>, out: &mut Vec<u8>) {
         // clear output buffer
         out.clear();
         out.extend_from_slice(b"$HEX[");
-        out.extend_from_slice(&hex_encoded.as_slice());
+        out.extend_from_slice(hex_encoded.as_slice());
         out.push(b']');
     } else {
         *out = line
@@ -55,18 +69,309 @@ pub fn decode_hex_if_needed(mut line: Vec<u8>) -> (Vec<u8>, usize) {
     (line, line_len)
 }
 
+/// Like `decode_hex_if_needed` but without taking ownership of the line.
+/// `buf` is used to hold the decoded line, so it can be reused for every line.
+#[inline(always)]
+pub fn decode_hex_line<'a>(line: &'a [u8], buf: &'a mut Vec<u8>) -> &'a [u8] {
+    if line.len() >= 6 && line.starts_with(b"$HEX[") && line.ends_with(b"]") {
+        buf.clear();
+        buf.resize((line.len() - 6) / 2, 0);
+        if hex_decode(&line[5..line.len() - 1], buf).is_ok() {
+            return buf;
+        }
+        // not valid $HEX encoding, treat as "normal" password
+    }
+    line
+}
+
+/// A regular file is memory-mapped, everything else (stdin, pipes, ...) is read buffered
+pub enum Input {
+    Mapped(Mmap),
+    Reader(Box<dyn BufRead>),
+}
+
+impl Input {
+    /// Calls `f` for every line, without the line terminator. Exits on read errors,
+    /// `name` is the input shown in the message.
+    pub fn for_each_line<F: FnMut(&[u8])>(self, name: &str, mut f: F) {
+        match self {
+            Input::Mapped(map) => {
+                for line in ByteSlice::lines(&map[..]) {
+                    f(line);
+                }
+            }
+            Input::Reader(reader) => {
+                let result = reader.for_byte_line(|line| {
+                    f(line);
+                    Ok(true)
+                });
+                if let Err(e) = result {
+                    read_error(name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Name of an input in messages, `stdin` if there is no file
+pub fn input_name(input: &Option<PathBuf>) -> String {
+    match input {
+        Some(path) => path.display().to_string(),
+        None => "stdin".to_string(),
+    }
+}
+
+/// Exits after an input couldn't be read, e.g. because it's corrupt or truncated
+pub fn read_error(name: &str, e: io::Error) -> ! {
+    eprintln!("[!] can't read '{}': {}", name, e);
+    process::exit(-1);
+}
+
+/// `visited` holds the canonical path of every directory read so far, so symlinks
+/// pointing to an ancestor or to an already read directory are skipped
+fn expand_input(path: PathBuf, files: &mut Vec<PathBuf>, visited: &mut HashSet<PathBuf>) {
+    if !path.is_dir() {
+        files.push(path);
+        return;
+    }
+    if let Ok(canonical) = fs::canonicalize(&path) {
+        if !visited.insert(canonical) {
+            return;
+        }
+    }
+    let mut entries: Vec<PathBuf> = match fs::read_dir(&path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|e| e.path())
+            .collect(),
+        Err(e) => {
+            eprintln!("can't read directory '{}': {}", path.display(), e);
+            process::exit(-1);
+        }
+    };
+    entries.sort();
+    for entry in entries {
+        expand_input(entry, files, visited);
+    }
+}
+
+/// Replaces every directory by the files it contains (recursively, sorted by name)
+pub fn expand_inputs(inputs: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for input in inputs {
+        expand_input(input, &mut files, &mut visited);
+    }
+    files
+}
+
+/// Calls `f` for every line of all `inputs` as if they were one file, reads
+/// stdin if there are none. If there is more than one file the number of
+/// lines of each file is written to stderr.
+pub fn for_each_line<F: FnMut(&[u8])>(inputs: Vec<PathBuf>, mut f: F) {
+    let files = expand_inputs(inputs);
+    if files.is_empty() {
+        open_input(None).for_each_line("stdin", f);
+        return;
+    }
+    let report = files.len() > 1;
+    for file in files {
+        let mut lines: usize = 0;
+        let name = file.display().to_string();
+        open_input(Some(file.clone())).for_each_line(&name, |line| {
+            lines += 1;
+            f(line);
+        });
+        if report {
+            eprintln!("[*] {}: {} lines", file.display(), lines);
+        }
+    }
+}
+
+/// Exits if the file can't be opened or the compressed stream can't be set up
+pub fn open_input(input: Option<PathBuf>) -> Input {
+    let filename = match input {
+        None => return Input::Reader(get_reader(None)),
+        Some(filename) => filename,
+    };
+    let file = match File::open(&filename) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("can't open '{}': {}", filename.display(), e);
+            process::exit(-1);
+        }
+    };
+    let name = filename.display().to_string();
+    if file.metadata().map(|m| m.is_file()).unwrap_or(false) {
+        // mapping an empty file fails, it's read like any other input in that case
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            if Compression::from_magic(&map).is_none() {
+                return Input::Mapped(map);
+            }
+            return Input::Reader(decompress(Cursor::new(map), &name));
+        }
+    }
+    Input::Reader(decompress(BufReader::new(file), &name))
+}
+
+/// "BZh", the block size 1 - 9 and the magic of the first block, or of the end of
+/// the stream if nothing was compressed. Just "BZh" is a plausible start of a wordlist.
+fn is_bzip2(magic: &[u8]) -> bool {
+    const BLOCK_MAGIC: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END_MAGIC: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+    magic.len() >= 10
+        && magic.starts_with(b"BZh")
+        && (b'1'..=b'9').contains(&magic[3])
+        && (magic[4..10] == BLOCK_MAGIC || magic[4..10] == END_MAGIC)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_magic(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if is_bzip2(magic) {
+            Some(Compression::Bzip2)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_extension(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "bz2" => Some(Compression::Bzip2),
+            "xz" => Some(Compression::Xz),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps `reader` in a decoder if its first bytes are the magic of a supported compression
+fn decompress<R: BufRead + 'static>(mut reader: R, name: &str) -> Box<dyn BufRead> {
+    let compression = match reader.fill_buf() {
+        Ok(magic) => Compression::from_magic(magic),
+        Err(_) => None,
+    };
+    match compression {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Some(Compression::Bzip2) => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        Some(Compression::Xz) => Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader))),
+        Some(Compression::Zstd) => match zstd::Decoder::with_buffer(reader) {
+            Ok(decoder) => Box::new(BufReader::new(decoder)),
+            Err(e) => read_error(name, e),
+        },
+    }
+}
+
 pub fn get_reader(input: Option<PathBuf>) -> Box<dyn BufRead> {
-    let reader: Box<dyn BufRead> = match input {
-        None => Box::new(BufReader::new(io::stdin())),
-        Some(filename) => Box::new(BufReader::new(File::open(filename).unwrap())),
+    let name = input_name(&input);
+    match input {
+        None => decompress(BufReader::new(io::stdin()), &name),
+        Some(filename) => match File::open(&filename) {
+            Ok(file) => decompress(BufReader::new(file), &name),
+            Err(e) => {
+                eprintln!("can't open '{}': {}", name, e);
+                process::exit(-1);
+            }
+        },
+    }
+}
+
+/// Reads an .hcmask file, empty lines and lines starting with `#` are ignored.
+/// Exits on the first invalid mask.
+pub fn read_hcmask(input: PathBuf, custom: &mask::CustomCharsets) -> mask::MaskSet {
+    let reader = match File::open(&input) {
+        Ok(file) => decompress(BufReader::new(file), &input.display().to_string()),
+        Err(e) => {
+            eprintln!("can't open '{}': {}", input.display(), e);
+            process::exit(-1);
+        }
     };
-    reader
+
+    let mut masks = mask::MaskSet::new();
+    for (line_num, line) in reader.byte_lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => read_error(&input.display().to_string(), e),
+        };
+        let line = line.trim_end_with(|c| c == '\r');
+        if line.is_empty() || line[0] == b'#' {
+            continue;
+        }
+        match mask::parse_hcmask(line, custom) {
+            Ok(parsed) => masks.push(line.to_vec(), parsed),
+            Err(e) => {
+                eprintln!(
+                    "invalid mask in '{}' line {}: {}",
+                    input.display(),
+                    line_num + 1,
+                    e
+                );
+                process::exit(-1);
+            }
+        }
+    }
+    masks
 }
 
+/// Reads at least `size` bytes (unless EOF is reached) into `buf`, always
+/// ending on a complete line. Returns false if nothing was left to read.
+pub fn read_lines_chunk(
+    reader: &mut Box<dyn BufRead>,
+    buf: &mut Vec<u8>,
+    size: usize,
+) -> io::Result<bool> {
+    buf.clear();
+    while buf.len() < size {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Ok(!buf.is_empty());
+        }
+        let len = available.len().min(size - buf.len());
+        buf.extend_from_slice(&available[..len]);
+        reader.consume(len);
+    }
+    if !buf.ends_with(b"\n") {
+        reader.read_until(b'\n', buf)?;
+    }
+    Ok(true)
+}
+
+/// Compresses the output if the file name ends in .gz, .bz2, .xz or .zst
 pub fn get_writer(output: Option<PathBuf>) -> Box<dyn Write> {
-    let writer: Box<dyn Write> = match output {
-        None => Box::new(BufWriter::new(io::stdout())),
-        Some(filename) => Box::new(BufWriter::new(File::create(filename).unwrap())),
+    let filename = match output {
+        None => return Box::new(BufWriter::new(io::stdout())),
+        Some(filename) => filename,
+    };
+    let file = File::create(&filename).unwrap();
+    let writer: Box<dyn Write> = match Compression::from_extension(&filename) {
+        None => Box::new(BufWriter::new(file)),
+        Some(Compression::Gzip) => Box::new(BufWriter::new(GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        ))),
+        Some(Compression::Bzip2) => Box::new(BufWriter::new(BzEncoder::new(
+            file,
+            bzip2::Compression::default(),
+        ))),
+        Some(Compression::Xz) => Box::new(BufWriter::new(XzEncoder::new(file, 6))),
+        Some(Compression::Zstd) => Box::new(BufWriter::new(
+            zstd::Encoder::new(file, 0).unwrap().auto_finish(),
+        )),
     };
     writer
 }
@@ -86,6 +391,19 @@ pub fn mywrite(data: &mut &[u8], writer: &mut Box<dyn Write>) {
     }
 }
 
+/// Formats a duration given in seconds as e.g. "3d 04:05:06"
+pub fn format_runtime(seconds: u128) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = seconds % 3600 / 60;
+    let seconds = seconds % 60;
+    if days > 0 {
+        format!("{}d {:02}:{:02}:{:02}", days, hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    }
+}
+
 pub fn get_bitmap2string() -> Vec<&'static str> {
     let bitmap2string: Vec<&str> = vec![
         "invalid",
@@ -168,3 +486,48 @@ pub const CHAR2SMASK: [u8; 256] = [
     16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
     16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_from_magic() {
+        assert_eq!(Compression::from_magic(b"BZhello\nworld\n"), None);
+        assert_eq!(Compression::from_magic(b"BZh9"), None);
+        assert_eq!(
+            Compression::from_magic(b"BZh91AY&SY\x00"),
+            Some(Compression::Bzip2)
+        );
+        // an empty stream only contains the end of stream magic
+        assert_eq!(
+            Compression::from_magic(b"BZh9\x17\x72\x45\x38\x50\x90"),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x1f, 0x8b, 0x08]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::from_magic(b"password"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expand_inputs_skips_symlink_cycles() {
+        use std::os::unix::fs::symlink;
+
+        let dir = std::env::temp_dir().join(format!("pack2-expand-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "a\n").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "b\n").unwrap();
+        symlink("..", dir.join("sub").join("up")).unwrap();
+        symlink(".", dir.join("self")).unwrap();
+
+        let files = expand_inputs(vec![dir.clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec![dir.join("a.txt"), dir.join("sub").join("b.txt")]
+        );
+    }
+}
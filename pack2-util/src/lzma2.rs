@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+use std::mem;
+use std::ptr;
+
+use lzma_sys::*;
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Compresses to a raw LZMA2 stream without the .xz container, as expected by
+/// hashcat for .hcstat2 files (`xz --format=raw`). The stream is only complete
+/// after `finish` has been called.
+pub struct Lzma2Encoder<W: Write> {
+    stream: lzma_stream,
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Lzma2Encoder<W> {
+    pub fn new(writer: W, preset: u32) -> io::Result<Lzma2Encoder<W>> {
+        unsafe {
+            let mut options: lzma_options_lzma = mem::zeroed();
+            if lzma_lzma_preset(&mut options, preset) != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unsupported lzma preset",
+                ));
+            }
+            let filters = [
+                lzma_filter {
+                    id: LZMA_FILTER_LZMA2,
+                    options: &mut options as *mut _ as *mut _,
+                },
+                lzma_filter {
+                    id: LZMA_VLI_UNKNOWN,
+                    options: ptr::null_mut(),
+                },
+            ];
+            let mut stream: lzma_stream = mem::zeroed();
+            let ret = lzma_raw_encoder(&mut stream, filters.as_ptr());
+            if ret != LZMA_OK {
+                return Err(lzma_error(ret));
+            }
+            Ok(Lzma2Encoder {
+                stream,
+                writer,
+                buf: vec![0; BUF_SIZE],
+            })
+        }
+    }
+
+    fn code(&mut self, input: &[u8], action: lzma_action) -> io::Result<lzma_ret> {
+        self.stream.next_in = input.as_ptr();
+        self.stream.avail_in = input.len();
+        self.stream.next_out = self.buf.as_mut_ptr();
+        self.stream.avail_out = self.buf.len();
+        let ret = unsafe { lzma_code(&mut self.stream, action) };
+        if ret != LZMA_OK && ret != LZMA_STREAM_END {
+            return Err(lzma_error(ret));
+        }
+        let written = self.buf.len() - self.stream.avail_out;
+        self.writer.write_all(&self.buf[..written])?;
+        Ok(ret)
+    }
+
+    /// Writes the remaining data including the end marker
+    pub fn finish(mut self) -> io::Result<()> {
+        while self.code(&[], LZMA_FINISH)? != LZMA_STREAM_END {}
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Write for Lzma2Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut input = data;
+        while !input.is_empty() {
+            let before = input.len();
+            self.code(input, LZMA_RUN)?;
+            input = &input[before - self.stream.avail_in..];
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Drop for Lzma2Encoder<W> {
+    fn drop(&mut self) {
+        unsafe { lzma_end(&mut self.stream) }
+    }
+}
+
+fn lzma_error(ret: lzma_ret) -> io::Error {
+    io::Error::other(format!("lzma error {}", ret))
+}
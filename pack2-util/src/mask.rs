@@ -0,0 +1,516 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{utf8, CHAR2BITMAP, CHAR2MASK};
+
+/// A set of bytes, one bit per byte value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Charset([u64; 4]);
+
+impl Charset {
+    pub fn empty() -> Charset {
+        Charset([0; 4])
+    }
+
+    pub fn from_range(from: u8, to: u8) -> Charset {
+        let mut charset = Charset::empty();
+        for byte in from..=to {
+            charset.insert(byte);
+        }
+        charset
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Charset {
+        let mut charset = Charset::empty();
+        for byte in bytes {
+            charset.insert(*byte);
+        }
+        charset
+    }
+
+    /// Returns the built-in hashcat charset for ?l, ?u, ?d, ?h, ?H, ?s, ?a and ?b
+    pub fn builtin(c: u8) -> Option<Charset> {
+        let charset = match c {
+            b'l' => Charset::from_range(b'a', b'z'),
+            b'u' => Charset::from_range(b'A', b'Z'),
+            b'd' => Charset::from_range(b'0', b'9'),
+            b'h' => Charset::from_range(b'0', b'9').union(&Charset::from_range(b'a', b'f')),
+            b'H' => Charset::from_range(b'0', b'9').union(&Charset::from_range(b'A', b'F')),
+            b's' => Charset::from_bitmap(8),
+            b'a' => Charset::from_bitmap(15),
+            b'b' => Charset::from_range(0x00, 0xff),
+            _ => return None,
+        };
+        Some(charset)
+    }
+
+    /// Collects every byte whose `CHAR2BITMAP` class is part of `bitmap`
+    fn from_bitmap(bitmap: u8) -> Charset {
+        let mut charset = Charset::empty();
+        for (byte, class) in CHAR2BITMAP.iter().enumerate() {
+            if class & bitmap != 0 {
+                charset.insert(byte as u8);
+            }
+        }
+        charset
+    }
+
+    #[inline(always)]
+    pub fn insert(&mut self, byte: u8) {
+        self.0[(byte >> 6) as usize] |= 1 << (byte & 0x3f);
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, byte: u8) -> bool {
+        self.0[(byte >> 6) as usize] & (1 << (byte & 0x3f)) != 0
+    }
+
+    pub fn union(&self, other: &Charset) -> Charset {
+        let mut charset = *self;
+        for (bits, other_bits) in charset.0.iter_mut().zip(other.0.iter()) {
+            *bits |= other_bits;
+        }
+        charset
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|bits| *bits == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=255u8).filter(move |byte| self.contains(*byte))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskError {
+    Empty,
+    TrailingQuestionMark,
+    UnknownCharset(char),
+    UndefinedCustomCharset(char),
+    TooManyCharsets,
+}
+
+impl fmt::Display for MaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MaskError::Empty => write!(f, "mask is empty"),
+            MaskError::TrailingQuestionMark => {
+                write!(f, "mask ends with a single '?', use '??' for a literal '?'")
+            }
+            MaskError::UnknownCharset(c) => write!(f, "unknown charset '?{}'", c),
+            MaskError::UndefinedCustomCharset(c) => {
+                write!(f, "custom charset '?{}' used but not defined (-{})", c, c)
+            }
+            MaskError::TooManyCharsets => write!(f, "more than 4 custom charsets defined"),
+        }
+    }
+}
+
+impl Error for MaskError {}
+
+/// The user-defined charsets ?1 - ?4
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CustomCharsets([Option<Charset>; 4]);
+
+impl CustomCharsets {
+    pub fn new() -> CustomCharsets {
+        CustomCharsets([None; 4])
+    }
+
+    /// Parses the definitions given by -1 to -4.
+    /// Like hashcat, a custom charset may refer to the ones defined before it.
+    pub fn parse(definitions: &[Option<String>; 4]) -> Result<CustomCharsets, MaskError> {
+        let mut custom = CustomCharsets::new();
+        for (idx, definition) in definitions.iter().enumerate() {
+            if let Some(definition) = definition {
+                custom.define(idx as u8 + 1, definition.as_bytes())?;
+            }
+        }
+        Ok(custom)
+    }
+
+    /// Defines ?`n` as the union of all positions of `definition`
+    pub fn define(&mut self, n: u8, definition: &[u8]) -> Result<(), MaskError> {
+        let mut charset = Charset::empty();
+        for token in parse_tokens(definition, self)? {
+            charset = charset.union(&token.charset());
+        }
+        self.0[n as usize - 1] = Some(charset);
+        Ok(())
+    }
+
+    pub fn get(&self, n: u8) -> Option<Charset> {
+        self.0.get((n as usize).wrapping_sub(1)).copied().flatten()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskToken {
+    /// A static character
+    Literal(u8),
+    /// A built-in charset, e.g. `b'l'` for ?l
+    Builtin(u8),
+    /// A user-defined charset ?1 - ?4 together with the bytes it resolved to
+    Custom(u8, Charset),
+}
+
+impl MaskToken {
+    pub fn charset(&self) -> Charset {
+        match self {
+            MaskToken::Literal(byte) => Charset::from_bytes(&[*byte]),
+            MaskToken::Builtin(c) => Charset::builtin(*c).unwrap_or_default(),
+            MaskToken::Custom(_, charset) => *charset,
+        }
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        match self {
+            MaskToken::Literal(b'?') => out.extend_from_slice(b"??"),
+            MaskToken::Literal(byte) => out.push(*byte),
+            MaskToken::Builtin(c) => out.extend_from_slice(&[b'?', *c]),
+            MaskToken::Custom(n, _) => out.extend_from_slice(&[b'?', b'0' + n]),
+        }
+    }
+}
+
+fn parse_tokens(mask: &[u8], custom: &CustomCharsets) -> Result<Vec<MaskToken>, MaskError> {
+    let mut tokens: Vec<MaskToken> = Vec::new();
+    let mut bytes = mask.iter();
+
+    while let Some(byte) = bytes.next() {
+        if *byte != b'?' {
+            tokens.push(MaskToken::Literal(*byte));
+            continue;
+        }
+        let token = match bytes.next() {
+            None => return Err(MaskError::TrailingQuestionMark),
+            Some(b'?') => MaskToken::Literal(b'?'),
+            Some(n @ b'1'..=b'4') => match custom.get(n - b'0') {
+                None => return Err(MaskError::UndefinedCustomCharset(*n as char)),
+                Some(charset) => MaskToken::Custom(n - b'0', charset),
+            },
+            Some(c) => match Charset::builtin(*c) {
+                None => return Err(MaskError::UnknownCharset(*c as char)),
+                Some(_) => MaskToken::Builtin(*c),
+            },
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// A hashcat mask, one token per position
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mask {
+    tokens: Vec<MaskToken>,
+    charsets: Vec<Charset>,
+}
+
+impl Mask {
+    pub fn parse(mask: &[u8], custom: &CustomCharsets) -> Result<Mask, MaskError> {
+        let tokens = parse_tokens(mask, custom)?;
+        if tokens.is_empty() {
+            return Err(MaskError::Empty);
+        }
+        Ok(Mask::from_tokens(tokens))
+    }
+
+    pub fn from_tokens(tokens: Vec<MaskToken>) -> Mask {
+        let charsets = tokens.iter().map(|token| token.charset()).collect();
+        Mask { tokens, charsets }
+    }
+
+    /// Builds the mask of a password, e.g. `?u?l?l?d` for "Abc1"
+    pub fn from_password(line: &[u8]) -> Mask {
+        Mask::from_classes(line.iter().map(|byte| CHAR2MASK[*byte as usize]))
+    }
+
+    /// Builds a mask from built-in charset names, e.g. `b"ulld"` for `?u?l?l?d`
+    pub fn from_classes<I: IntoIterator<Item = u8>>(classes: I) -> Mask {
+        Mask::from_tokens(classes.into_iter().map(MaskToken::Builtin).collect())
+    }
+
+    pub fn tokens(&self) -> &[MaskToken] {
+        &self.tokens
+    }
+
+    /// Number of positions, not the length of the mask string
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, line: &[u8]) -> bool {
+        line.len() == self.charsets.len()
+            && line
+                .iter()
+                .zip(self.charsets.iter())
+                .all(|(byte, charset)| charset.contains(*byte))
+    }
+
+    /// Like `matches` but every position is a code point instead of a byte.
+    /// The built-in charsets ?l, ?u, ?d, ?s and ?a match by Unicode category
+    /// (see `utf8::char2mask`), static characters and all other charsets only
+    /// match single byte characters.
+    pub fn matches_utf8(&self, line: &[u8]) -> bool {
+        let mut chars = utf8::chars(line);
+        for (token, charset) in self.tokens.iter().zip(self.charsets.iter()) {
+            let (bytes, c) = match chars.next() {
+                None => return false,
+                Some(next) => next,
+            };
+            let matched = match token {
+                MaskToken::Builtin(b'b') => true,
+                MaskToken::Builtin(b'a') => utf8::char2mask(c) != b'b',
+                MaskToken::Builtin(class @ b'l')
+                | MaskToken::Builtin(class @ b'u')
+                | MaskToken::Builtin(class @ b'd')
+                | MaskToken::Builtin(class @ b's') => utf8::char2mask(c) == *class,
+                _ => bytes.len() == 1 && charset.contains(bytes[0]),
+            };
+            if !matched {
+                return false;
+            }
+        }
+        chars.next().is_none()
+    }
+
+    /// Number of candidates the mask generates, saturating at `u128::MAX`
+    pub fn keyspace(&self) -> u128 {
+        self.charsets.iter().fold(1u128, |keyspace, charset| {
+            keyspace.saturating_mul(charset.len() as u128)
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.tokens.len() * 2);
+        for token in &self.tokens {
+            token.write_to(&mut out);
+        }
+        out
+    }
+}
+
+impl FromStr for Mask {
+    type Err = MaskError;
+
+    fn from_str(mask: &str) -> Result<Mask, MaskError> {
+        Mask::parse(mask.as_bytes(), &CustomCharsets::new())
+    }
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+/// Parses a line of an .hcmask file: up to four comma separated custom charsets
+/// followed by the mask, e.g. `?l?d,?u,?2?1?1?1`. A literal comma is written as `\,`.
+/// Charsets defined in the line replace the ones given in `custom`.
+pub fn parse_hcmask(line: &[u8], custom: &CustomCharsets) -> Result<Mask, MaskError> {
+    let mut fields: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut bytes = line.iter().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' if bytes.peek() == Some(&&b',') => {
+                fields.last_mut().unwrap().push(b',');
+                bytes.next();
+            }
+            b',' => fields.push(Vec::new()),
+            _ => fields.last_mut().unwrap().push(*byte),
+        }
+    }
+
+    let mask = fields.pop().unwrap();
+    if fields.len() > 4 {
+        return Err(MaskError::TooManyCharsets);
+    }
+    let mut custom = *custom;
+    for (idx, definition) in fields.iter().enumerate() {
+        custom.define(idx as u8 + 1, definition)?;
+    }
+    Mask::parse(&mask, &custom)
+}
+
+/// A list of named masks indexed by length, to match lines against many masks at once
+#[derive(Clone, Debug, Default)]
+pub struct MaskSet {
+    masks: Vec<(Vec<u8>, Mask)>,
+    /// Indices into `masks` of every mask length, in insertion order
+    by_length: Vec<Vec<usize>>,
+}
+
+impl MaskSet {
+    pub fn new() -> MaskSet {
+        MaskSet::default()
+    }
+
+    /// Adds `mask`, `name` is usually the line it was parsed from
+    pub fn push(&mut self, name: Vec<u8>, mask: Mask) {
+        if self.by_length.len() <= mask.len() {
+            self.by_length.resize(mask.len() + 1, Vec::new());
+        }
+        self.by_length[mask.len()].push(self.masks.len());
+        self.masks.push((name, mask));
+    }
+
+    pub fn len(&self) -> usize {
+        self.masks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masks.is_empty()
+    }
+
+    pub fn name(&self, idx: usize) -> &[u8] {
+        &self.masks[idx].0
+    }
+
+    pub fn mask(&self, idx: usize) -> &Mask {
+        &self.masks[idx].1
+    }
+
+    /// Index of the first mask (in insertion order) matching `line`
+    #[inline(always)]
+    pub fn first_match(&self, line: &[u8]) -> Option<usize> {
+        self.by_length
+            .get(line.len())?
+            .iter()
+            .copied()
+            .find(|idx| self.masks[*idx].1.matches(line))
+    }
+
+    /// Like `first_match` using `Mask::matches_utf8`
+    pub fn first_match_utf8(&self, line: &[u8]) -> Option<usize> {
+        self.by_length
+            .get(utf8::char_count(line))?
+            .iter()
+            .copied()
+            .find(|idx| self.masks[*idx].1.matches_utf8(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(mask: &str) -> Result<Mask, MaskError> {
+        Mask::parse(mask.as_bytes(), &CustomCharsets::new())
+    }
+
+    #[test]
+    fn question_marks() {
+        let mask = parse("a??b").unwrap();
+        assert_eq!(mask.len(), 3);
+        assert!(mask.matches(b"a?b"));
+        assert!(!mask.matches(b"axb"));
+        assert_eq!(mask.to_string(), "a??b");
+
+        assert_eq!(parse("?l?"), Err(MaskError::TrailingQuestionMark));
+        assert_eq!(parse("?"), Err(MaskError::TrailingQuestionMark));
+        assert_eq!(parse(""), Err(MaskError::Empty));
+    }
+
+    #[test]
+    fn hex_and_unknown_charsets() {
+        let lower = parse("?h?h").unwrap();
+        assert!(lower.matches(b"0f"));
+        assert!(!lower.matches(b"0F"));
+        assert!(!lower.matches(b"0g"));
+
+        let upper = parse("?H?H").unwrap();
+        assert!(upper.matches(b"A9"));
+        assert!(!upper.matches(b"a9"));
+
+        assert_eq!(parse("?l?x"), Err(MaskError::UnknownCharset('x')));
+        assert_eq!(parse("?5"), Err(MaskError::UnknownCharset('5')));
+    }
+
+    #[test]
+    fn custom_charsets() {
+        assert_eq!(parse("?1?d"), Err(MaskError::UndefinedCustomCharset('1')));
+
+        // ?2 refers to ?1, which has to be defined before it
+        let definitions = [
+            Some("?dab".to_string()),
+            Some("?1?u".to_string()),
+            None,
+            None,
+        ];
+        let custom = CustomCharsets::parse(&definitions).unwrap();
+        let mask = Mask::parse(b"?2?1", &custom).unwrap();
+        assert!(mask.matches(b"Za"));
+        assert!(mask.matches(b"b7"));
+        assert!(!mask.matches(b"Zc"));
+        assert_eq!(mask.to_string(), "?2?1");
+        assert_eq!(custom.get(2).unwrap().len(), 10 + 2 + 26);
+
+        let forward = [None, Some("?1".to_string()), None, None];
+        assert_eq!(
+            CustomCharsets::parse(&forward),
+            Err(MaskError::UndefinedCustomCharset('1'))
+        );
+        assert_eq!(
+            Mask::parse(b"?3", &custom),
+            Err(MaskError::UndefinedCustomCharset('3'))
+        );
+    }
+
+    #[test]
+    fn keyspace() {
+        assert_eq!(parse("?s").unwrap().keyspace(), 33);
+        assert_eq!(parse("?b").unwrap().keyspace(), 256);
+        assert_eq!(parse("?a").unwrap().keyspace(), 95);
+        assert_eq!(parse("?l?d").unwrap().keyspace(), 260);
+        assert_eq!(parse("abc").unwrap().keyspace(), 1);
+        assert_eq!(parse(&"?b".repeat(20)).unwrap().keyspace(), u128::MAX);
+    }
+
+    #[test]
+    fn hcmask_lines() {
+        let custom = CustomCharsets::new();
+
+        let mask = parse_hcmask(b"?d\\,,?1?1", &custom).unwrap();
+        assert!(mask.matches(b",5"));
+        assert!(!mask.matches(b"a5"));
+
+        let mask = parse_hcmask(b"a\\,b", &custom).unwrap();
+        assert!(mask.matches(b"a,b"));
+
+        let mask = parse_hcmask(b"?l,?u,?2?1", &custom).unwrap();
+        assert!(mask.matches(b"Xy"));
+
+        assert_eq!(
+            parse_hcmask(b"a,b,c,d,e,?1", &custom),
+            Err(MaskError::TooManyCharsets)
+        );
+        assert!(parse_hcmask(b"a,b,c,d,?4?1", &custom).is_ok());
+    }
+
+    #[test]
+    fn first_match_in_insertion_order() {
+        let mut masks = MaskSet::new();
+        for mask in &["?d?d?d?d", "?l?l?l?l", "?a?a?a?a", "?l?l"] {
+            masks.push(mask.as_bytes().to_vec(), parse(mask).unwrap());
+        }
+        assert_eq!(masks.first_match(b"1234"), Some(0));
+        assert_eq!(masks.first_match(b"test"), Some(1));
+        assert_eq!(masks.first_match(b"Te5t"), Some(2));
+        assert_eq!(masks.first_match(b"ab"), Some(3));
+        assert_eq!(masks.first_match(b"abc"), None);
+        assert_eq!(masks.first_match(b"abcdefgh"), None);
+        assert_eq!(masks.name(2), b"?a?a?a?a");
+    }
+}
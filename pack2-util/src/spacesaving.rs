@@ -0,0 +1,289 @@
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::hash::Hash;
+
+use hashbrown::HashMap;
+
+/// Estimated count of a key, the true count is within `count - error ..= count`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Counter {
+    pub count: u64,
+    pub error: u64,
+}
+
+/// Approximate counts of the most frequent keys using at most `capacity` counters
+/// (Space-Saving, Metwally et al.). Every key occurring more often than
+/// `min_count()` is guaranteed to be kept.
+#[derive(Clone, Debug)]
+pub struct SpaceSaving<K: Hash + Eq + Ord + Clone> {
+    capacity: usize,
+    counters: HashMap<K, Counter>,
+    /// One entry per counter, the count may be outdated as counters are increased in place
+    heap: BinaryHeap<Reverse<(u64, K)>>,
+    /// Upper bound of the count of every key without a counter
+    min_count: u64,
+}
+
+impl<K: Hash + Eq + Ord + Clone> SpaceSaving<K> {
+    pub fn new(capacity: usize) -> SpaceSaving<K> {
+        let capacity = capacity.max(1);
+        SpaceSaving {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+            heap: BinaryHeap::with_capacity(capacity),
+            min_count: 0,
+        }
+    }
+
+    /// Restores a summary from its counters as returned by `iter` and `min_count`.
+    /// Only the largest `capacity` counters are kept.
+    pub fn from_counters<I>(capacity: usize, min_count: u64, counters: I) -> SpaceSaving<K>
+    where
+        I: IntoIterator<Item = (K, Counter)>,
+    {
+        let mut summary = SpaceSaving::new(capacity);
+        summary.counters.extend(counters);
+        summary.min_count = min_count;
+        summary.truncate();
+        summary
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Upper bound of the count of every key not kept
+    pub fn min_count(&self) -> u64 {
+        self.min_count
+    }
+
+    pub fn add<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(counter) = self.counters.get_mut(key) {
+            counter.count += 1;
+            return;
+        }
+
+        // the new key takes over the counter with the smallest count
+        if self.counters.len() >= self.capacity {
+            let min = self.remove_min();
+            self.min_count = self.min_count.max(min.count);
+        }
+        let counter = Counter {
+            count: self.min_count + 1,
+            error: self.min_count,
+        };
+        let key = key.to_owned();
+        self.heap.push(Reverse((counter.count, key.clone())));
+        self.counters.insert(key, counter);
+    }
+
+    fn remove_min(&mut self) -> Counter {
+        loop {
+            let Reverse((count, key)) = self.heap.pop().unwrap();
+            let current = self.counters[&key].count;
+            if current == count {
+                return self.counters.remove(&key).unwrap();
+            }
+            self.heap.push(Reverse((current, key)));
+        }
+    }
+
+    /// Drops all but the largest `capacity` counters and rebuilds the heap
+    fn truncate(&mut self) {
+        if self.counters.len() > self.capacity {
+            // ties are ordered by key to stay deterministic
+            let mut counters: Vec<(K, Counter)> = self.counters.drain().collect();
+            counters.sort_unstable_by(|(a_key, a), (b_key, b)| {
+                b.count.cmp(&a.count).then_with(|| a_key.cmp(b_key))
+            });
+            for (_, dropped) in counters.drain(self.capacity..) {
+                self.min_count = self.min_count.max(dropped.count);
+            }
+            self.counters = counters.into_iter().collect();
+        }
+
+        self.heap = self
+            .counters
+            .iter()
+            .map(|(key, counter)| Reverse((counter.count, key.clone())))
+            .collect();
+    }
+
+    /// Merges two summaries of disjoint inputs, the error bounds add up.
+    /// A key missing in one summary may have occurred up to its `min_count()` times there.
+    pub fn merge(&mut self, other: SpaceSaving<K>) {
+        let self_min = self.min_count;
+        let other_min = other.min_count;
+
+        for (key, counter) in self.counters.iter_mut() {
+            if !other.counters.contains_key(key) {
+                counter.count += other_min;
+                counter.error += other_min;
+            }
+        }
+        for (key, other_counter) in other.counters {
+            let counter = self.counters.entry(key).or_insert(Counter {
+                count: self_min,
+                error: self_min,
+            });
+            counter.count += other_counter.count;
+            counter.error += other_counter.error;
+        }
+
+        self.min_count = self_min + other_min;
+        self.truncate();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Counter)> {
+        self.counters.iter()
+    }
+
+    pub fn into_counters(self) -> impl Iterator<Item = (K, Counter)> {
+        self.counters.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 40 keys, key i occurring 600 / (i + 1) times, in a fixed pseudo-random order
+    fn skewed_stream() -> Vec<u32> {
+        let mut stream: Vec<u32> = (0..40u32)
+            .flat_map(|key| std::iter::repeat_n(key, 600 / (key as usize + 1)))
+            .collect();
+        let mut state: u64 = 42;
+        for i in (1..stream.len()).rev() {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            stream.swap(i, (state >> 33) as usize % (i + 1));
+        }
+        stream
+    }
+
+    fn true_counts(stream: &[u32]) -> HashMap<u32, u64> {
+        let mut counts = HashMap::new();
+        for key in stream {
+            *counts.entry(*key).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// `count - error <= true count <= count` for every kept key and every key
+    /// occurring more often than `min_count` is kept
+    fn check_bounds(summary: &SpaceSaving<u32>, counts: &HashMap<u32, u64>) {
+        for (key, counter) in summary.iter() {
+            let count = counts[key];
+            assert!(
+                counter.count - counter.error <= count,
+                "{}: {:?}",
+                key,
+                counter
+            );
+            assert!(count <= counter.count, "{}: {:?} < {}", key, counter, count);
+        }
+        for (key, count) in counts {
+            if *count > summary.min_count() {
+                assert!(summary.iter().any(|(k, _)| k == key), "{} missing", key);
+            }
+        }
+        assert!(summary.len() <= summary.capacity());
+    }
+
+    #[test]
+    fn evicts_the_smallest_counter() {
+        let mut summary = SpaceSaving::new(2);
+        for key in &[1u32, 1, 2, 3] {
+            summary.add(key);
+        }
+        // 3 took over the counter of 2, inheriting its count as error
+        let mut counters: Vec<(u32, Counter)> = summary.into_counters().collect();
+        counters.sort_unstable();
+        let expected = vec![
+            (1, Counter { count: 2, error: 0 }),
+            (3, Counter { count: 2, error: 1 }),
+        ];
+        assert_eq!(counters, expected);
+    }
+
+    #[test]
+    fn bounds_on_skewed_stream() {
+        let stream = skewed_stream();
+        let counts = true_counts(&stream);
+        let mut summary = SpaceSaving::new(10);
+        for key in &stream {
+            summary.add(key);
+        }
+        check_bounds(&summary, &counts);
+        // the most frequent keys stand out clearly
+        for key in 0..3 {
+            assert!(summary.iter().any(|(k, _)| *k == key));
+        }
+    }
+
+    #[test]
+    fn merge_adds_error_bounds() {
+        let stream = skewed_stream();
+        let (first, second) = stream.split_at(stream.len() / 3);
+        let mut summary = SpaceSaving::new(10);
+        let mut other = SpaceSaving::new(10);
+        first.iter().for_each(|key| summary.add(key));
+        second.iter().for_each(|key| other.add(key));
+        let min_count = summary.min_count() + other.min_count();
+
+        // dropping the counters past the capacity may raise it further
+        summary.merge(other);
+        assert!(summary.min_count() >= min_count);
+        check_bounds(&summary, &true_counts(&stream));
+
+        // a key missing in one summary gets its min_count as count and error
+        let mut summary =
+            SpaceSaving::from_counters(2, 0, vec![(1u32, Counter { count: 5, error: 0 })]);
+        let other = SpaceSaving::from_counters(2, 3, vec![(2u32, Counter { count: 4, error: 3 })]);
+        summary.merge(other);
+        let mut counters: Vec<(u32, Counter)> = summary.into_counters().collect();
+        counters.sort_unstable();
+        let expected = vec![
+            (1, Counter { count: 8, error: 3 }),
+            (2, Counter { count: 4, error: 3 }),
+        ];
+        assert_eq!(counters, expected);
+    }
+
+    #[test]
+    fn truncate_keeps_the_largest_counters() {
+        let counters = vec![
+            (1u32, Counter { count: 5, error: 0 }),
+            (2, Counter { count: 3, error: 1 }),
+            (3, Counter { count: 2, error: 0 }),
+            (4, Counter { count: 1, error: 0 }),
+        ];
+        let mut summary = SpaceSaving::from_counters(2, 0, counters);
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary.min_count(), 2);
+
+        // the rebuilt heap evicts the smallest remaining counter
+        summary.add(&5);
+        let mut counters: Vec<(u32, Counter)> = summary.into_counters().collect();
+        counters.sort_unstable();
+        let expected = vec![
+            (1, Counter { count: 5, error: 0 }),
+            (5, Counter { count: 4, error: 3 }),
+        ];
+        assert_eq!(counters, expected);
+    }
+}
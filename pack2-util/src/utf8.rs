@@ -0,0 +1,60 @@
+use bstr::ByteSlice;
+
+use crate::CHAR2MASK;
+
+/// Iterates the code points of `line` together with their bytes.
+/// Invalid UTF-8 sequences are returned as `None`.
+#[inline(always)]
+pub fn chars(line: &[u8]) -> impl Iterator<Item = (&[u8], Option<char>)> {
+    line.char_indices().map(move |(start, end, c)| {
+        let bytes = &line[start..end];
+        // invalid sequences are substituted by U+FFFD which is 3 bytes long itself
+        if c == '\u{FFFD}' && bytes != "\u{FFFD}".as_bytes() {
+            (bytes, None)
+        } else {
+            (bytes, Some(c))
+        }
+    })
+}
+
+/// Number of code points in `line`, invalid sequences count as one each
+pub fn char_count(line: &[u8]) -> usize {
+    line.char_indices().count()
+}
+
+/// Classifies a code point by its Unicode category like `CHAR2MASK` classifies a byte.
+/// Letters without case count as lower case, control characters and invalid
+/// sequences as binary and everything else (punctuation, symbols, spaces) as special.
+#[inline(always)]
+pub fn char2mask(c: Option<char>) -> u8 {
+    match c {
+        None => b'b',
+        Some(c) if c.is_ascii() => CHAR2MASK[c as usize],
+        Some(c) if c.is_uppercase() => b'u',
+        Some(c) if c.is_alphabetic() => b'l',
+        Some(c) if c.is_numeric() => b'd',
+        Some(c) if c.is_control() => b'b',
+        Some(_) => b's',
+    }
+}
+
+/// Like `CHAR2BITMAP` for a code point
+#[inline(always)]
+pub fn char2bitmap(c: Option<char>) -> u8 {
+    match char2mask(c) {
+        b'l' => 1,
+        b'u' => 2,
+        b'd' => 4,
+        b's' => 8,
+        _ => 16,
+    }
+}
+
+/// Like `CHAR2SMASK` for a code point
+#[inline(always)]
+pub fn char2smask(c: Option<char>) -> u8 {
+    match char2bitmap(c) {
+        2 => 1,
+        bitmap => bitmap,
+    }
+}
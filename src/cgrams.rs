@@ -1,135 +1,124 @@
 use std::iter::FromIterator;
-use std::path::PathBuf;
 
-use bstr::{io::BufReadExt, ByteSlice};
-use faster_hex::hex_encode;
+use bstr::ByteSlice;
 use hashbrown::HashMap;
+use pack2_util::utf8;
 use pack2_util::*;
 
-pub fn gen_c_grams(
-    input: Option<PathBuf>,
-    output: Option<PathBuf>,
-    sort: bool,
-    ignore_case: bool,
-    normalize: bool,
-) {
-    let reader = get_reader(input);
-    let mut writer = get_writer(output);
-
-    let mut c_gram = [0u8; 0xffff];
-    let mut out_hex = [0u8; 0xffff * 2];
-    let mut c_grams: HashMap<Vec<u8>, u64> = HashMap::new();
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Don't split on a change between lower and upper case
+    pub ignore_case: bool,
+    /// Also count the lower case version of every c-gram containing upper case
+    pub normalize: bool,
+    /// Split on code points classified by their Unicode category instead of bytes
+    pub utf8: bool,
+}
 
-    let lookup_table;
-    match ignore_case {
-        false => lookup_table = CHAR2BITMAP,
-        true => lookup_table = CHAR2SMASK,
+/// Splits `line` into c-grams, a new one starts whenever the charset changes
+fn c_grams<'a>(line: &'a [u8], options: &Options) -> Vec<&'a [u8]> {
+    if line.is_empty() {
+        return Vec::new();
+    }
+    let lookup_table = match options.ignore_case {
+        false => CHAR2BITMAP,
+        true => CHAR2SMASK,
     };
+    let lookup_utf8 = match options.ignore_case {
+        false => utf8::char2bitmap,
+        true => utf8::char2smask,
+    };
+    match options.utf8 {
+        false => split_line(line, line.iter().map(|c| (1, lookup_table[*c as usize]))),
+        true => split_line(
+            line,
+            utf8::chars(line).map(|(bytes, c)| (bytes.len(), lookup_utf8(c))),
+        ),
+    }
+}
 
-    for result in reader.byte_lines() {
-        let (line, line_len) = decode_hex_if_needed(result.unwrap());
-        if line_len > u16::MAX.into() || line_len == 0 {
-            continue;
-        }
+/// Splits `line` whenever the charset changes, `units` are the length in
+/// bytes and the charset of every character
+fn split_line<I: Iterator<Item = (usize, u8)>>(line: &[u8], units: I) -> Vec<&[u8]> {
+    let mut c_grams = Vec::new();
+    let mut last_charset: u8 = 0;
+    let mut start: usize = 0;
+    let mut pos: usize = 0;
 
-        let mut last_charset: u8 = 0;
-        let mut idx: usize = 0;
+    for (len, cur_charset) in units {
+        if last_charset != 0 && last_charset != cur_charset {
+            c_grams.push(&line[start..pos]);
+            start = pos;
+        }
+        last_charset = cur_charset;
+        pos += len;
+    }
+    c_grams.push(&line[start..pos]);
+    c_grams
+}
 
-        for c in line.iter() {
-            let cur_charset = lookup_table[*c as usize];
+/// The lower case version of `c_gram` if it contains upper case and `normalize` is set
+fn normalized(c_gram: &[u8], options: &Options) -> Option<Vec<u8>> {
+    let uppercase =
+        contains_uppercase(c_gram) || (options.utf8 && c_gram.chars().any(char::is_uppercase));
+    match options.normalize && uppercase {
+        true => Some(c_gram.to_lowercase()),
+        false => None,
+    }
+}
 
-            if last_charset == cur_charset {
-                c_gram[idx] = *c;
-                idx += 1;
-                continue;
-            }
-            if last_charset == 0 {
-                c_gram[idx] = *c;
-                idx += 1;
-                last_charset = cur_charset;
-                continue;
-            }
-            if last_charset != cur_charset {
-                // || idx_total == line_len {
-                if sort {
-                    *c_grams.entry(c_gram[..idx].to_vec()).or_insert(0) += 1;
-                    if normalize {
-                        let c_gram_vec = c_gram[..idx].to_vec();
-                        if contains_uppercase(&c_gram_vec) {
-                            let lower = c_gram_vec.to_lowercase();
-                            *c_grams.entry(lower).or_insert(0) += 1;
-                        }
-                    }
-                } else {
-                    // $HEX[] encoding necessary
-                    if last_charset == 16 {
-                        let _ = hex_encode(&c_gram[..idx], &mut out_hex);
-                        let out = &*format!("$HEX[{}]\n", &out_hex[..idx * 2].to_str().unwrap());
-                        mywrite(&mut out.as_bytes(), &mut writer);
-                    } else {
-                        c_gram[idx] = b'\n';
-                        mywrite(&mut c_gram[..=idx].as_bytes(), &mut writer);
-                        if normalize {
-                            let c_gram_vec = c_gram[..=idx].to_vec();
-                            if contains_uppercase(&c_gram_vec) {
-                                let lower = c_gram_vec.to_lowercase();
-                                mywrite(&mut lower.as_bytes(), &mut writer);
-                            }
-                        }
-                    }
-                }
-                c_gram[0] = *c;
-                idx = 1;
-                last_charset = cur_charset;
-            }
+/// Calls `f` with every c-gram of `line` and, with `normalize`, the lower case version
+/// of those containing upper case. `$HEX[]` encoded lines are decoded first.
+pub fn for_each_c_gram<F: FnMut(&[u8])>(line: &[u8], options: &Options, mut f: F) {
+    let mut hex_buf: Vec<u8> = Vec::new();
+    let line = decode_hex_line(line, &mut hex_buf);
+    for c_gram in c_grams(line, options) {
+        f(c_gram);
+        if let Some(lower) = normalized(c_gram, options) {
+            f(&lower);
         }
-        // TODO: This is really ugly as it's duplicate code form what we have in the above loop.
-        // Either find a better way to construct the loop or move this code into it's own function.
-        if sort {
-            *c_grams.entry(c_gram[..idx].to_vec()).or_insert(0) += 1;
-            if normalize {
-                let c_gram_vec = c_gram[..idx].to_vec();
-                if contains_uppercase(&c_gram_vec) {
-                    let lower = c_gram_vec.to_lowercase();
-                    *c_grams.entry(lower).or_insert(0) += 1;
-                }
-            }
-        } else {
-            // $HEX[] encoding necessary
-            if last_charset == 16 {
-                let _ = hex_encode(&c_gram[..idx], &mut out_hex);
-                let out = &*format!("$HEX[{}]\n", &out_hex[..idx * 2].to_str().unwrap());
-                mywrite(&mut out.as_bytes(), &mut writer);
-            } else {
-                c_gram[idx] = b'\n';
-                mywrite(&mut c_gram[..=idx].as_bytes(), &mut writer);
-                if normalize {
-                    let c_gram_vec = c_gram[..=idx].to_vec();
-                    if contains_uppercase(&c_gram_vec) {
-                        let lower = c_gram_vec.to_lowercase();
-                        mywrite(&mut lower.as_bytes(), &mut writer);
-                    }
-                }
-            }
+    }
+}
+
+/// Number of occurrences of every c-gram
+pub struct CGramCounts {
+    options: Options,
+    counts: HashMap<Vec<u8>, u64>,
+}
+
+impl CGramCounts {
+    pub fn new(options: Options) -> CGramCounts {
+        CGramCounts {
+            options,
+            counts: HashMap::new(),
         }
     }
 
-    if sort {
-        let mut out = Vec::new();
-        let mut freq_c_grams = Vec::from_iter(c_grams);
-        freq_c_grams.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
+    pub fn add_line(&mut self, line: &[u8]) {
+        let counts = &mut self.counts;
+        for_each_c_gram(line, &self.options, |c_gram| {
+            *counts.entry(c_gram.to_vec()).or_insert(0) += 1;
+        });
+    }
 
-        eprintln!("\n[*] c-grams (top 25):");
-        let mut top = 0;
+    /// The c-grams by count, descending
+    pub fn into_sorted(self) -> Vec<(Vec<u8>, u64)> {
+        let mut sorted = Vec::from_iter(self.counts);
+        sorted.sort_unstable_by(|(a_c_gram, a), (b_c_gram, b)| {
+            b.cmp(a).then_with(|| a_c_gram.cmp(b_c_gram))
+        });
+        sorted
+    }
+}
 
-        for (c_gram, count) in freq_c_grams {
-            encode_hex_if_needed(c_gram, &mut out);
-            if top < 25 {
-                eprintln!("[+] {: >26}: ({})", &out.to_str().unwrap(), count);
-                top += 1;
-            }
-            let out = &*format!("{}\t{}\n", &out.to_str().unwrap(), count);
-            mywrite(&mut out.as_bytes(), &mut writer);
-        }
+/// Counts the c-grams of all `lines` and returns them by count, descending
+pub fn count_c_grams<'a, I>(lines: I, options: &Options) -> Vec<(Vec<u8>, u64)>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut counts = CGramCounts::new(*options);
+    for line in lines {
+        counts.add_line(line);
     }
+    counts.into_sorted()
 }
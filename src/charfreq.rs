@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use pack2_util::*;
+
+#[derive(Debug)]
+pub struct Options {
+    /// Number of positions counted from the start of the line
+    pub positions: usize,
+    /// Number of characters of every .hcchr file and the summary on stderr
+    pub top: usize,
+    /// Directory the .hcchr files are written to
+    pub hcchr: Option<PathBuf>,
+}
+
+/// Byte frequencies of one section, e.g. of all characters or the first one
+struct Frequencies {
+    name: String,
+    counts: [u64; 256],
+}
+
+impl Frequencies {
+    fn new(name: String) -> Frequencies {
+        Frequencies {
+            name,
+            counts: [0; 256],
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Bytes that occurred at least once, most frequent first
+    fn sorted(&self) -> Vec<(u8, u64)> {
+        let mut sorted: Vec<(u8, u64)> = (0..=255u8)
+            .map(|byte| (byte, self.counts[byte as usize]))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        sorted.sort_by(|(a_byte, a), (b_byte, b)| b.cmp(a).then_with(|| a_byte.cmp(b_byte)));
+        sorted
+    }
+
+    fn top(&self, k: usize) -> Vec<u8> {
+        self.sorted()
+            .into_iter()
+            .take(k)
+            .map(|(byte, _)| byte)
+            .collect()
+    }
+}
+
+fn write_hcchr(dir: &PathBuf, sections: &[Frequencies], top: usize) {
+    if let Err(e) = fs::create_dir_all(dir) {
+        eprintln!("can't create '{}': {}", dir.display(), e);
+        process::exit(-1);
+    }
+    for section in sections {
+        let path = dir.join(format!("{}.hcchr", section.name));
+        if let Err(e) = fs::write(&path, section.top(top)) {
+            eprintln!("can't write '{}': {}", path.display(), e);
+            process::exit(-1);
+        }
+    }
+}
+
+pub fn charfreq(input: Vec<PathBuf>, output: Option<PathBuf>, options: &Options) {
+    let mut all = Frequencies::new("all".to_string());
+    let mut first = Frequencies::new("first".to_string());
+    let mut last = Frequencies::new("last".to_string());
+    let mut positions: Vec<Frequencies> = (1..=options.positions)
+        .map(|pos| Frequencies::new(format!("pos{}", pos)))
+        .collect();
+    let mut hex_buf: Vec<u8> = Vec::new();
+
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        if line.is_empty() {
+            return;
+        }
+        first.counts[line[0] as usize] += 1;
+        last.counts[line[line.len() - 1] as usize] += 1;
+        for (byte, position) in line.iter().zip(positions.iter_mut()) {
+            position.counts[*byte as usize] += 1;
+        }
+        for byte in line {
+            all.counts[*byte as usize] += 1;
+        }
+    });
+
+    let mut sections = vec![all, first, last];
+    sections.extend(positions.into_iter().filter(|pos| pos.total() > 0));
+
+    let mut writer = get_writer(output);
+    let mut out: Vec<u8> = Vec::new();
+    for section in &sections {
+        let total = section.total();
+        for (byte, count) in section.sorted() {
+            encode_hex_if_needed(vec![byte], &mut out);
+            let line = format!(
+                "{}\t{}\t{}\t{:.4}\n",
+                section.name,
+                String::from_utf8_lossy(&out),
+                count,
+                100.0 / total as f64 * count as f64
+            );
+            mywrite(&mut line.as_bytes(), &mut writer);
+        }
+    }
+
+    eprintln!("[*] Top {} characters:", options.top);
+    for section in &sections {
+        encode_hex_if_needed(section.top(options.top), &mut out);
+        eprintln!(
+            "[+] {: >26}: {}",
+            section.name,
+            String::from_utf8_lossy(&out)
+        );
+    }
+
+    if let Some(dir) = &options.hcchr {
+        write_hcchr(dir, &sections, options.top);
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use bstr::ByteSlice;
+use pack2::cgrams::{for_each_c_gram, CGramCounts, Options};
+use pack2_util::*;
+
+struct Writer {
+    writer: Box<dyn Write>,
+    utf8: bool,
+    out: Vec<u8>,
+}
+
+impl Writer {
+    /// In UTF-8 mode valid UTF-8 without control characters is written as is,
+    /// everything else is $HEX[] encoded if needed
+    fn encode(&mut self, c_gram: &[u8]) {
+        let printable_utf8 =
+            self.utf8 && c_gram.to_str().is_ok() && !c_gram.chars().any(char::is_control);
+        if printable_utf8 {
+            self.out.clear();
+            self.out.extend_from_slice(c_gram);
+        } else {
+            encode_hex_if_needed(c_gram.to_vec(), &mut self.out);
+        }
+    }
+
+    fn write(&mut self, c_gram: &[u8]) {
+        self.encode(c_gram);
+        self.out.push(b'\n');
+        mywrite(&mut self.out.as_slice(), &mut self.writer);
+    }
+
+    fn write_sorted(&mut self, c_grams: Vec<(Vec<u8>, u64)>) {
+        eprintln!("\n[*] c-grams (top 25):");
+        let mut top = 0;
+
+        for (c_gram, count) in c_grams {
+            self.encode(&c_gram);
+            let out = self.out.to_str().unwrap();
+            if top < 25 {
+                eprintln!("[+] {: >26}: ({})", out, count);
+                top += 1;
+            }
+            let out = &*format!("{}\t{}\n", out, count);
+            mywrite(&mut out.as_bytes(), &mut self.writer);
+        }
+    }
+}
+
+pub fn gen_c_grams(
+    input: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    sort: bool,
+    ignore_case: bool,
+    normalize: bool,
+    utf8: bool,
+) {
+    let options = Options {
+        ignore_case,
+        normalize,
+        utf8,
+    };
+    let mut writer = Writer {
+        writer: get_writer(output),
+        utf8,
+        out: Vec::new(),
+    };
+
+    if sort {
+        let mut counts = CGramCounts::new(options);
+        for_each_line(input, |line| counts.add_line(line));
+        writer.write_sorted(counts.into_sorted());
+        return;
+    }
+
+    for_each_line(input, |line| {
+        for_each_c_gram(line, &options, |c_gram| writer.write(c_gram));
+    });
+}
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use std::process;
+
+use pack2::filtermask::{filter_line, Options};
+use pack2_util::mask::{CustomCharsets, Mask, MaskSet};
+use pack2_util::*;
+
+pub fn filtermask(
+    input: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    mask: Option<String>,
+    mask_file: Option<PathBuf>,
+    custom_charsets: [Option<String>; 4],
+    options: &Options,
+) {
+    let custom = match CustomCharsets::parse(&custom_charsets) {
+        Ok(custom) => custom,
+        Err(e) => {
+            eprintln!("invalid custom charset: {}", e);
+            process::exit(-1);
+        }
+    };
+
+    let filters = match (mask, mask_file) {
+        (_, Some(mask_file)) => read_hcmask(mask_file, &custom),
+        (Some(mask), None) => match Mask::parse(mask.as_bytes(), &custom) {
+            Ok(filter) => {
+                let mut filters = MaskSet::new();
+                filters.push(mask.into_bytes(), filter);
+                filters
+            }
+            Err(e) => {
+                eprintln!("invalid mask '{}': {}", mask, e);
+                process::exit(-1);
+            }
+        },
+        (None, None) => {
+            eprintln!("no mask given, use <mask> or --mask-file");
+            process::exit(-1);
+        }
+    };
+    if filters.is_empty() {
+        eprintln!("no masks found in mask file");
+        process::exit(-1);
+    }
+
+    let mut writer = get_writer(output);
+    let mut hex_buf: Vec<u8> = Vec::new();
+    let mut out: Vec<u8> = Vec::new();
+
+    let mut processed = 0;
+    let mut total_lines = 0;
+
+    for_each_line(input, |line| {
+        total_lines += 1;
+
+        let matched = match filter_line(&filters, line, options) {
+            Some(matched) => matched,
+            None => return,
+        };
+
+        processed += 1;
+        out.clear();
+        out.extend_from_slice(decode_hex_line(matched.line, &mut hex_buf));
+        if let (true, Some(idx)) = (options.tag, matched.mask) {
+            out.push(b'\t');
+            out.extend_from_slice(filters.name(idx));
+        }
+        out.push(b'\n');
+        mywrite(&mut out.as_slice(), &mut writer);
+    });
+    let skipped = total_lines - processed;
+    eprintln!(
+        "wrote {} out of {} lines. Skipped: {}",
+        processed, total_lines, skipped
+    );
+}
@@ -0,0 +1,6 @@
+//! The subcommands built on the analyses of the library, reading their input
+//! from files or stdin and writing the results.
+
+pub mod cgrams;
+pub mod filtermask;
+pub mod statsgen;
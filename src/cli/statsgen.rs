@@ -0,0 +1,461 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use bstr::ByteSlice;
+use pack2::statsgen::{Options, Report, Stats};
+use pack2_util::*;
+
+/// Size of the chunks of lines handed to the worker threads
+const CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Statistics on stderr, masks written to the output
+    Text,
+    /// A single JSON document written to the output
+    Json,
+    /// A single CSV table written to the output
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format '{}', use text, json or csv", s)),
+        }
+    }
+}
+
+/// Settings of the subcommands besides the analysis and report `Options`
+#[derive(Debug)]
+pub struct CliOptions {
+    pub format: Format,
+    pub separator: Option<char>,
+    pub threads: usize,
+    /// Write the raw counters to this file
+    pub snapshot: Option<PathBuf>,
+}
+
+/// Splits `data` into chunks of about `CHUNK_SIZE` bytes, each ending on a complete line
+fn split_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = (start + CHUNK_SIZE).min(data.len());
+        if let Some(newline) = data[end..].find_byte(b'\n') {
+            end += newline + 1;
+        } else {
+            end = data.len();
+        }
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+/// Analyzes the chunks of a memory-mapped input on `threads` workers, each
+/// taking the next chunk not yet analyzed
+fn collect_stats_mapped(data: &[u8], options: &Options, threads: usize) -> Stats {
+    let chunks = split_chunks(data);
+    let next_chunk = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut stats = Stats::with_options(options);
+                    while let Some(chunk) = chunks.get(next_chunk.fetch_add(1, Ordering::Relaxed)) {
+                        stats.add_chunk(chunk);
+                    }
+                    stats
+                })
+            })
+            .collect();
+
+        let mut stats = Stats::with_options(options);
+        for worker in workers {
+            stats.merge(worker.join().unwrap());
+        }
+        stats
+    })
+}
+
+/// Reads the input in chunks of lines which are analyzed by `threads` workers,
+/// each collecting its own `Stats` which are merged at the end.
+fn collect_stats_reader(
+    mut reader: Box<dyn BufRead>,
+    name: &str,
+    options: &Options,
+    threads: usize,
+) -> Stats {
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(threads * 2);
+    let receiver = Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut stats = Stats::with_options(options);
+                    loop {
+                        let chunk = match receiver.lock().unwrap().recv() {
+                            Ok(chunk) => chunk,
+                            Err(_) => break,
+                        };
+                        stats.add_chunk(&chunk);
+                    }
+                    stats
+                })
+            })
+            .collect();
+
+        loop {
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE + 256);
+            match read_lines_chunk(&mut reader, &mut chunk, CHUNK_SIZE) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => read_error(name, e),
+            }
+            sender.send(chunk).unwrap();
+        }
+        drop(sender);
+
+        let mut stats = Stats::with_options(options);
+        for worker in workers {
+            stats.merge(worker.join().unwrap());
+        }
+        stats
+    })
+}
+
+fn collect_file_stats(input: Option<PathBuf>, options: &Options, threads: usize) -> Stats {
+    let name = input_name(&input);
+    match open_input(input) {
+        Input::Mapped(map) => collect_stats_mapped(&map, options, threads),
+        Input::Reader(reader) => collect_stats_reader(reader, &name, options, threads),
+    }
+}
+
+fn collect_stats(input: Vec<PathBuf>, options: &Options, threads: usize) -> Stats {
+    let files = expand_inputs(input);
+    if files.is_empty() {
+        return collect_file_stats(None, options, threads);
+    }
+
+    let mut stats = Stats::with_options(options);
+    let report = files.len() > 1;
+    for file in files {
+        let file_stats = collect_file_stats(Some(file.clone()), options, threads);
+        if report {
+            eprintln!("[*] {}: {} lines", file.display(), file_stats.lines());
+        }
+        stats.merge(file_stats);
+    }
+    stats
+}
+
+/// Bytes of a --max-memory budget in MiB for each of `stats` `Stats` sharing it
+pub fn memory_per_stats(max_memory: Option<usize>, stats: usize) -> Option<usize> {
+    max_memory.map(|mib| (mib << 20) / stats)
+}
+
+pub fn gen(input: Vec<PathBuf>, output: Option<PathBuf>, options: &Options, cli: &CliOptions) {
+    let stats = collect_stats(input, options, cli.threads);
+    write_report(stats, output, options, cli);
+}
+
+fn read_snapshot(input: Option<PathBuf>) -> Stats {
+    let name = input_name(&input);
+    match serde_json::from_reader(get_reader(input)) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("invalid snapshot '{}': {}", name, e);
+            process::exit(-1);
+        }
+    }
+}
+
+/// Combines the snapshots written by statsgen --snapshot and writes the usual report
+pub fn merge_snapshots(
+    input: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    options: &Options,
+    cli: &CliOptions,
+) {
+    let mut files: Vec<Option<PathBuf>> = expand_inputs(input).into_iter().map(Some).collect();
+    if files.is_empty() {
+        files.push(None);
+    }
+
+    let mut stats: Option<Stats> = None;
+    for file in files {
+        let name = input_name(&file);
+        let mut file_stats = read_snapshot(file);
+        file_stats.approximate_masks(options);
+        stats = match stats {
+            None => Some(file_stats),
+            Some(mut stats) => {
+                // the counters of different settings can't be combined into one report
+                if stats.utf8() != file_stats.utf8() {
+                    eprintln!("snapshot '{}' differs in --utf8 from the ones before", name);
+                    process::exit(-1);
+                }
+                if stats.simple_mask_options() != file_stats.simple_mask_options() {
+                    eprintln!(
+                        "snapshot '{}' differs in the simple mask options from the ones before",
+                        name
+                    );
+                    process::exit(-1);
+                }
+                stats.merge(file_stats);
+                Some(stats)
+            }
+        };
+    }
+    write_report(stats.unwrap(), output, options, cli);
+}
+
+fn write_report(stats: Stats, output: Option<PathBuf>, options: &Options, cli: &CliOptions) {
+    if let Some(snapshot) = &cli.snapshot {
+        let mut out = serde_json::to_vec(&stats).unwrap();
+        out.push(b'\n');
+        mywrite(&mut out.as_slice(), &mut get_writer(Some(snapshot.clone())));
+    }
+    let report = stats.report(options);
+
+    let mut writer = get_writer(output);
+    match cli.format {
+        Format::Text => write_text(&report, cli.separator.unwrap_or('\t'), &mut writer),
+        Format::Json => write_json(&report, &mut writer),
+        Format::Csv => write_csv(&report, options.efficiency, &mut writer),
+    }
+}
+
+fn write_text(report: &Report, separator: char, writer: &mut Box<dyn Write>) {
+    eprintln!(
+        "[+] Analyzed {} / {} passwords.",
+        report.analyzed, report.total
+    );
+
+    eprintln!(
+        "[*] Length distribution: (min: {} max: {})",
+        report.min_length, report.max_length
+    );
+    for entry in &report.lengths {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% ({})",
+            entry.length, entry.percent, entry.count
+        );
+    }
+
+    let summary = &report.length_summary;
+    eprintln!("\n[*] Length statistics:");
+    eprintln!("[+] {: >26}: {:.2}", "mean", summary.mean);
+    eprintln!("[+] {: >26}: {:.2}", "stddev", summary.stddev);
+    eprintln!("[+] {: >26}: {}", "median", summary.median);
+    eprintln!("[+] {: >26}: {}", "p90", summary.p90);
+    eprintln!("[+] {: >26}: {}", "p99", summary.p99);
+
+    for distribution in &report.class_counts {
+        eprintln!("\n[*] Number of {} characters:", distribution.class);
+        for entry in &distribution.counts {
+            eprintln!(
+                "[+] {: >26}: {: >6.2}% ({})",
+                entry.characters, entry.percent, entry.count
+            );
+        }
+    }
+
+    eprintln!("\n[*] Charset distribution:                    count   min   max");
+    for entry in &report.charsets {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% {: >10} {: >5} {: >5}",
+            entry.charset, entry.percent, entry.count, entry.min_length, entry.max_length
+        );
+    }
+
+    eprintln!("\n[*] Simple masks distribution:               count   min   max");
+    for entry in &report.simple_masks {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% {: >10} {: >5} {: >5}",
+            entry.simple_mask, entry.percent, entry.count, entry.min_length, entry.max_length
+        );
+    }
+
+    if !report.long_simple_masks.is_empty() || report.unlisted_long_simple_masks.is_some() {
+        eprintln!("\n[*] Simple masks past the cap (top 25):      count   min   max");
+        if let Some(unlisted) = report.unlisted_long_simple_masks {
+            eprintln!(
+                "[*] Limited by --max-memory, {} passwords with other simple masks not listed",
+                unlisted
+            );
+        }
+        for entry in report.long_simple_masks.iter().take(25) {
+            eprintln!(
+                "[+] {: >26}: {: >6.2}% {: >10} {: >5} {: >5}",
+                entry.simple_mask, entry.percent, entry.count, entry.min_length, entry.max_length
+            );
+        }
+    }
+
+    eprintln!("\n[*] Masks (top 25):                          count             keyspace");
+    if let Some(max_unlisted_count) = report.max_unlisted_count {
+        eprintln!(
+            "[*] Approximate counts, masks not listed occurred at most {} times",
+            max_unlisted_count
+        );
+    }
+    for entry in report.masks.iter().take(25) {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% {: >10} {: >20}",
+            entry.mask, entry.percent, entry.count, entry.keyspace
+        );
+    }
+
+    for entry in &report.masks {
+        let mut out = format!(
+            "{}{}{:.4}{}{}{}{}",
+            entry.mask, separator, entry.percent, separator, entry.count, separator, entry.keyspace
+        );
+        if let Some(efficiency) = entry.efficiency {
+            out.push_str(&format!("{}{:.4e}", separator, efficiency));
+        }
+        if let Some(error) = entry.error {
+            out.push_str(&format!("{}{}", separator, error));
+        }
+        out.push('\n');
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    match report.max_unlisted_count {
+        None => eprintln!(
+            "\n[*] Wrote {} of {} distinct masks.",
+            report.masks.len(),
+            report.distinct_masks
+        ),
+        Some(_) => eprintln!(
+            "\n[*] Wrote {} of {} tracked masks, the counts are approximate.",
+            report.masks.len(),
+            report.distinct_masks
+        ),
+    }
+}
+
+fn write_json(report: &Report, writer: &mut Box<dyn Write>) {
+    let mut out = serde_json::to_vec_pretty(report).unwrap();
+    out.push(b'\n');
+    mywrite(&mut out.as_slice(), writer);
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes all sections as one table: section, key, count, percent, min, max, keyspace
+/// and with approximate mask counts the error of the count
+fn write_csv(report: &Report, efficiency: bool, writer: &mut Box<dyn Write>) {
+    // the efficiency and error columns are left empty for all other sections
+    let mut pad = String::new();
+    let mut out = String::from("section,key,count,percent,min_length,max_length,keyspace");
+    if efficiency {
+        pad.push(',');
+        out.push_str(",efficiency");
+    }
+    if report.max_unlisted_count.is_some() {
+        pad.push(',');
+        out.push_str(",error");
+    }
+    out.push('\n');
+    out.push_str(&format!("total,,{},,,,{}\n", report.total, pad));
+    out.push_str(&format!(
+        "analyzed,,{},,{},{},{}\n",
+        report.analyzed, report.min_length, report.max_length, pad
+    ));
+    out.push_str(&format!("skipped,,{},,,,{}\n", report.skipped, pad));
+    mywrite(&mut out.as_bytes(), writer);
+
+    for entry in &report.lengths {
+        let out = format!(
+            "length,{},{},{:.4},,,{}\n",
+            entry.length, entry.count, entry.percent, pad
+        );
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    let summary = &report.length_summary;
+    let out = format!(
+        "length_summary,mean,{:.4},,,,{}\nlength_summary,stddev,{:.4},,,,{}\n",
+        summary.mean, pad, summary.stddev, pad
+    );
+    mywrite(&mut out.as_bytes(), writer);
+    for (key, value) in &[
+        ("median", summary.median),
+        ("p90", summary.p90),
+        ("p99", summary.p99),
+    ] {
+        let out = format!("length_summary,{},{},,,,{}\n", key, value, pad);
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    for distribution in &report.class_counts {
+        for entry in &distribution.counts {
+            let out = format!(
+                "{}_count,{},{},{:.4},,,{}\n",
+                distribution.class, entry.characters, entry.count, entry.percent, pad
+            );
+            mywrite(&mut out.as_bytes(), writer);
+        }
+    }
+    for entry in &report.charsets {
+        let out = format!(
+            "charset,{},{},{:.4},{},{},{}\n",
+            entry.charset, entry.count, entry.percent, entry.min_length, entry.max_length, pad
+        );
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    for entry in &report.simple_masks {
+        let out = format!(
+            "simple_mask,{},{},{:.4},{},{},{}\n",
+            entry.simple_mask, entry.count, entry.percent, entry.min_length, entry.max_length, pad
+        );
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    for entry in &report.long_simple_masks {
+        let out = format!(
+            "long_simple_mask,{},{},{:.4},{},{},{}\n",
+            entry.simple_mask, entry.count, entry.percent, entry.min_length, entry.max_length, pad
+        );
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    if let Some(unlisted) = report.unlisted_long_simple_masks {
+        let out = format!("unlisted_long_simple_masks,,{},,,,{}\n", unlisted, pad);
+        mywrite(&mut out.as_bytes(), writer);
+    }
+    for entry in &report.masks {
+        let mut out = format!(
+            "mask,{},{},{:.4},,,{}",
+            csv_field(&entry.mask),
+            entry.count,
+            entry.percent,
+            entry.keyspace
+        );
+        if let Some(efficiency) = entry.efficiency {
+            out.push_str(&format!(",{:.4e}", efficiency));
+        }
+        if let Some(error) = entry.error {
+            out.push_str(&format!(",{}", error));
+        }
+        out.push('\n');
+        mywrite(&mut out.as_bytes(), writer);
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use encoding_rs::{WINDOWS_1251, WINDOWS_1252};
+use pack2_util::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codepage {
+    Ascii,
+    Utf8,
+    Cp1252,
+    Latin1,
+    Cp1251,
+}
+
+const CODEPAGES: [Codepage; 5] = [
+    Codepage::Ascii,
+    Codepage::Utf8,
+    Codepage::Cp1252,
+    Codepage::Latin1,
+    Codepage::Cp1251,
+];
+
+impl FromStr for Codepage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Codepage, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" => Ok(Codepage::Ascii),
+            "utf8" | "utf-8" => Ok(Codepage::Utf8),
+            "cp1252" | "windows-1252" => Ok(Codepage::Cp1252),
+            "latin1" | "iso-8859-1" => Ok(Codepage::Latin1),
+            "cp1251" | "windows-1251" => Ok(Codepage::Cp1251),
+            _ => Err(format!(
+                "unknown encoding '{}', use ascii, utf8, cp1252, latin1 or cp1251",
+                s
+            )),
+        }
+    }
+}
+
+impl Codepage {
+    fn name(self) -> &'static str {
+        match self {
+            Codepage::Ascii => "ascii",
+            Codepage::Utf8 => "utf8",
+            Codepage::Cp1252 => "cp1252",
+            Codepage::Latin1 => "latin1",
+            Codepage::Cp1251 => "cp1251",
+        }
+    }
+
+    /// Decodes `line`, returns `None` if it isn't valid in this codepage
+    fn decode(self, line: &[u8]) -> Option<String> {
+        match self {
+            Codepage::Ascii if line.is_ascii() => Some(String::from_utf8(line.to_vec()).unwrap()),
+            Codepage::Ascii => None,
+            Codepage::Utf8 => String::from_utf8(line.to_vec()).ok(),
+            // encoding_rs treats latin1 as cp1252, so map the bytes to U+0000 - U+00FF ourselves
+            Codepage::Latin1 => Some(line.iter().map(|&c| c as char).collect()),
+            Codepage::Cp1252 => WINDOWS_1252
+                .decode_without_bom_handling_and_without_replacement(line)
+                .map(|s| s.into_owned()),
+            Codepage::Cp1251 => WINDOWS_1251
+                .decode_without_bom_handling_and_without_replacement(line)
+                .map(|s| s.into_owned()),
+        }
+    }
+
+    /// Encodes `line`, returns `None` if a character can't be represented in this codepage
+    fn encode(self, line: &str) -> Option<Vec<u8>> {
+        match self {
+            Codepage::Ascii if line.is_ascii() => Some(line.as_bytes().to_vec()),
+            Codepage::Ascii => None,
+            Codepage::Utf8 => Some(line.as_bytes().to_vec()),
+            Codepage::Latin1 => line
+                .chars()
+                .map(|c| match c as u32 {
+                    c @ 0..=0xff => Some(c as u8),
+                    _ => None,
+                })
+                .collect(),
+            Codepage::Cp1252 | Codepage::Cp1251 => {
+                let encoding = match self {
+                    Codepage::Cp1252 => WINDOWS_1252,
+                    _ => WINDOWS_1251,
+                };
+                match encoding.encode(line) {
+                    (_, _, true) => None,
+                    (encoded, _, false) => Some(encoded.into_owned()),
+                }
+            }
+        }
+    }
+}
+
+/// Number of consecutive Cyrillic letters needed to detect a line as cp1251
+const MIN_CYRILLIC_RUN: usize = 4;
+
+/// Guesses the encoding of a single line.
+///
+/// Lines that are neither ASCII nor valid UTF-8 are assumed to use a single byte codepage:
+/// cp1251 if Cyrillic letters (0xc0 - 0xff, 0xa8, 0xb8) outnumber the ASCII letters and
+/// at least `MIN_CYRILLIC_RUN` of them follow each other, cp1252 if any of 0x80 - 0x9f is
+/// used (control characters in latin1) and latin1 otherwise.
+pub fn detect(line: &[u8]) -> Codepage {
+    if line.is_ascii() {
+        return Codepage::Ascii;
+    }
+    if std::str::from_utf8(line).is_ok() {
+        return Codepage::Utf8;
+    }
+
+    // bytes >= 0xc0 are letters in cp1251 as well as in latin1 / cp1252, only lines
+    // mostly made of them with at least one longer run (a word) are taken as Cyrillic
+    let is_cyrillic = |c: u8| c >= 0xc0 || c == 0xa8 || c == 0xb8;
+    let ascii_letters = line.iter().filter(|c| c.is_ascii_alphabetic()).count();
+    let cyrillic_letters = line.iter().filter(|&&c| is_cyrillic(c)).count();
+    let longest_run = line
+        .split(|&c| !is_cyrillic(c))
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    if cyrillic_letters > ascii_letters && longest_run >= MIN_CYRILLIC_RUN {
+        Codepage::Cp1251
+    } else if line.iter().any(|&c| (0x80..=0x9f).contains(&c)) {
+        Codepage::Cp1252
+    } else {
+        Codepage::Latin1
+    }
+}
+
+pub fn encoding(
+    input: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    from: Option<Codepage>,
+    to: Option<Codepage>,
+) {
+    let mut writer = get_writer(output);
+    let mut hex_buf: Vec<u8> = Vec::new();
+    let mut out: Vec<u8> = Vec::new();
+
+    let mut total_lines: u64 = 0;
+    let mut counts = [0u64; CODEPAGES.len()];
+    let mut unconverted: u64 = 0;
+
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        let detected = detect(line);
+        total_lines += 1;
+        counts[detected as usize] += 1;
+
+        let to = match to {
+            Some(to) => to,
+            None => return,
+        };
+        // only the guess between the single byte codepages is overridden by --from
+        let from = match detected {
+            Codepage::Ascii | Codepage::Utf8 => detected,
+            _ => from.unwrap_or(detected),
+        };
+        let converted = match from.decode(line).and_then(|line| to.encode(&line)) {
+            Some(converted) => converted,
+            None => {
+                unconverted += 1;
+                line.to_vec()
+            }
+        };
+
+        encode_hex_if_needed(converted, &mut out);
+        out.push(b'\n');
+        mywrite(&mut out.as_slice(), &mut writer);
+    });
+
+    eprintln!("[*] Encoding distribution:");
+    for (codepage, count) in CODEPAGES.iter().zip(counts.iter()) {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% ({})",
+            codepage.name(),
+            100.0 / total_lines as f64 * *count as f64,
+            count
+        );
+    }
+
+    if let Some(to) = to {
+        eprintln!(
+            "\n[*] Converted {} / {} lines to {}, {} written unchanged.",
+            total_lines - unconverted,
+            total_lines,
+            to.name(),
+            unconverted
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_latin_letters() {
+        assert_eq!(detect(b"\xe9t\xe9"), Codepage::Latin1);
+        assert_eq!(detect(b"\xc4\xd6\xdc"), Codepage::Latin1);
+        assert_eq!(detect(b"M\xfcller"), Codepage::Latin1);
+        assert_eq!(detect(b"Stra\xdfe1"), Codepage::Latin1);
+        assert_eq!(detect(b"\x80uro\xe9"), Codepage::Cp1252);
+    }
+
+    #[test]
+    fn detects_cyrillic_words() {
+        // "пароль" and "привет" in cp1251
+        assert_eq!(detect(b"\xef\xe0\xf0\xee\xeb\xfc"), Codepage::Cp1251);
+        assert_eq!(detect(b"\xef\xf0\xe8\xe2\xe5\xf2123"), Codepage::Cp1251);
+        // a Cyrillic word next to a longer Latin one
+        assert_eq!(
+            detect(b"password\xef\xe0\xf0\xee\xeb\xfc"),
+            Codepage::Latin1
+        );
+    }
+
+    #[test]
+    fn converts_latin1_to_utf8() {
+        for (line, expected) in &[
+            (&b"\xe9t\xe9"[..], "\u{e9}t\u{e9}"),
+            (b"\xc4\xd6\xdc", "\u{c4}\u{d6}\u{dc}"),
+        ] {
+            let decoded = detect(line).decode(line).unwrap();
+            assert_eq!(decoded, *expected);
+        }
+    }
+}
@@ -1,61 +1,49 @@
-use std::path::PathBuf;
+use pack2_util::decode_hex_line;
+use pack2_util::mask::MaskSet;
+
+#[derive(Debug)]
+pub struct Options {
+    /// Write the lines not matching any mask instead
+    pub invert: bool,
+    /// Append the mask that matched to every line
+    pub tag: bool,
+    pub utf8: bool,
+}
 
-use bstr::{io::BufReadExt, ByteSlice};
-use pack2_util::*;
+/// A line passing the filter as given and the index of the first mask it matched,
+/// which is `None` with `invert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Matched<'a> {
+    pub line: &'a [u8],
+    pub mask: Option<usize>,
+}
 
-// TODO: This is a very basic mask "parser" we should make sure it's a valid mask.
-// We don't support ?h, ?H or custom masks yet.
-fn parse_mask(mask: String) -> (Vec<u8>, usize) {
-    let mask_len = mask.len() / 2;
-    let mut ret: Vec<u8> = Vec::new();
-    for c in mask.chars() {
-        match c {
-            'l' => ret.push(1),
-            'u' => ret.push(2),
-            'd' => ret.push(4),
-            's' => ret.push(8),
-            'a' => ret.push(15),
-            'b' => ret.push(31),
-            _ => (),
-        }
+/// Returns the line if it matches any of the masks, or with `invert` none of them.
+/// `$HEX[]` encoded lines are decoded before matching.
+pub fn filter_line<'a>(masks: &MaskSet, line: &'a [u8], options: &Options) -> Option<Matched<'a>> {
+    let mut hex_buf: Vec<u8> = Vec::new();
+    let decoded = decode_hex_line(line, &mut hex_buf);
+    let mask = match options.utf8 {
+        false => masks.first_match(decoded),
+        true => masks.first_match_utf8(decoded),
+    };
+    match mask.is_some() == options.invert {
+        true => None,
+        false => Some(Matched { line, mask }),
     }
-
-    (ret, mask_len)
 }
 
-pub fn filtermask(input: Option<PathBuf>, output: Option<PathBuf>, mask: String) {
-    let reader = get_reader(input);
-    let mut writer = get_writer(output);
-    let (filter, mask_len) = parse_mask(mask);
-
-    let mut processed = 0;
-    let mut skipped = 0;
-
-    for result in reader.byte_lines() {
-        let (mut line, line_len) = decode_hex_if_needed(result.unwrap());
-
-        if line_len != mask_len {
-            skipped += 1;
-            continue;
-        }
-
-        let mut matched = true;
-        for (idx, byte) in line.iter().enumerate() {
-            if filter[idx] & CHAR2BITMAP[*byte as usize] == 0 {
-                matched = false;
-                break;
-            }
-        }
-
-        if matched {
-            processed += 1;
-            line.push(b'\n');
-            mywrite(&mut line.as_bytes(), &mut writer);
-        }
-    }
-    let total_lines = processed + skipped;
-    eprintln!(
-        "wrote {} out of {} lines. Skipped: {}",
-        processed, total_lines, skipped
-    );
+/// The lines passing the filter, see `filter_line`
+pub fn filter<'a, I>(
+    lines: I,
+    masks: &'a MaskSet,
+    options: &'a Options,
+) -> impl Iterator<Item = Matched<'a>> + 'a
+where
+    I: IntoIterator<Item = &'a [u8]>,
+    I::IntoIter: 'a,
+{
+    lines
+        .into_iter()
+        .filter_map(move |line| filter_line(masks, line, options))
 }
@@ -0,0 +1,13 @@
+//! The analyses of the pack2 command line tool, usable on any iterator of lines:
+//!
+//! * `statsgen::analyze` collects `statsgen::Stats`, `Stats::report` turns them
+//!   into the length, charset, simple mask and mask distributions
+//! * `filtermask::filter` yields the lines matching a `pack2_util::mask::MaskSet`
+//! * `cgrams::count_c_grams` counts the c-grams of the lines
+//!
+//! All of them decode `$HEX[]` encoded lines first (see `pack2_util::decode_hex_line`).
+//! `Stats` can be serialized as snapshot of the raw counters and merged later.
+
+pub mod cgrams;
+pub mod filtermask;
+pub mod statsgen;
@@ -2,9 +2,16 @@ use std::path::PathBuf;
 use std::process;
 use structopt::StructOpt;
 
-mod cgrams;
-mod filtermask;
-mod statsgen;
+use pack2::{filtermask, statsgen};
+
+mod charfreq;
+mod cli;
+mod encoding;
+mod markovgen;
+mod maskcover;
+mod maskgen;
+mod pcfg;
+mod policygen;
 mod unhex;
 
 #[derive(StructOpt, Debug)]
@@ -14,9 +21,9 @@ pub enum CmdOpts {
     /// stats are written to stderr
     #[structopt(name = "statsgen")]
     Statsgen {
-        /// Input file, stdin if not present
+        /// Input files or directories, stdin if not present
         #[structopt(parse(from_os_str))]
-        input: Option<PathBuf>,
+        input: Vec<PathBuf>,
         /// Output file, stdout if not present
         #[structopt(short, long, parse(from_os_str), display_order = 1)]
         output: Option<PathBuf>,
@@ -25,41 +32,344 @@ pub enum CmdOpts {
         separator: Option<char>,
         /// Ignore passwords shorter than <min-length>
         #[structopt(long, default_value = "1", display_order = 13)]
-        min_length: u16,
-        /// Ignore passwords longer than <max-length>
+        min_length: usize,
+        /// Ignore passwords longer than <max-length> [default: no limit]
+        #[structopt(long, display_order = 14)]
+        max_length: Option<usize>,
+        /// Adds an efficiency column (count / keyspace) to the mask output
+        #[structopt(short, long, display_order = 3)]
+        efficiency: bool,
+        /// Output format: text (stats on stderr, masks to output), json or csv
+        #[structopt(short, long, default_value = "text", display_order = 4)]
+        format: cli::statsgen::Format,
+        /// Number of worker threads [default: number of CPUs]
+        #[structopt(short, long, display_order = 5)]
+        threads: Option<usize>,
+        /// Classify UTF-8 code points by their Unicode category, lengths are in characters
+        #[structopt(long, display_order = 6)]
+        utf8: bool,
+        /// Only keep the <top> most frequent entries of every section
+        #[structopt(long, display_order = 15)]
+        top: Option<usize>,
+        /// Ignore entries occurring less than <min-count> times
+        #[structopt(long, default_value = "0", display_order = 16)]
+        min_count: u64,
+        /// Ignore entries making up less than <min-percent> percent of the analyzed passwords
+        #[structopt(long, default_value = "0", display_order = 17)]
+        min_percent: f64,
+        /// Ignore masks with a keyspace larger than <max-keyspace>
+        #[structopt(long, display_order = 18)]
+        max_keyspace: Option<u128>,
+        /// Count masks approximately using about <max-memory> MiB, the output
+        /// includes the maximum error of every count
+        #[structopt(long, display_order = 7)]
+        max_memory: Option<usize>,
+        /// Writes the raw counters to <snapshot>, which can be combined later by statsmerge
+        #[structopt(long, parse(from_os_str), display_order = 8)]
+        snapshot: Option<PathBuf>,
+        /// Passwords with more segments are counted as othermask in the simple masks,
+        /// their full simple masks are listed in a separate section
+        #[structopt(long, default_value = "4", display_order = 19)]
+        simple_mask_segments: usize,
+        /// Distinguishes lower, upper and mixed case strings in the simple masks
+        #[structopt(long, display_order = 20)]
+        simple_mask_case: bool,
+        /// Counts binary characters as special in the simple masks
+        #[structopt(long, display_order = 21)]
+        fold_binary: bool,
+    },
+
+    /// Combines snapshots written by statsgen --snapshot and writes the statsgen report
+    #[structopt(name = "statsmerge")]
+    Statsmerge {
+        /// Snapshot files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str), display_order = 1)]
+        output: Option<PathBuf>,
+        /// Separator used in mask output [default: TAB]
+        #[structopt(short, long, display_order = 2)]
+        separator: Option<char>,
+        /// Adds an efficiency column (count / keyspace) to the mask output
+        #[structopt(short, long, display_order = 3)]
+        efficiency: bool,
+        /// Output format: text (stats on stderr, masks to output), json or csv
+        #[structopt(short, long, default_value = "text", display_order = 4)]
+        format: cli::statsgen::Format,
+        /// Only keep the <top> most frequent entries of every section
+        #[structopt(long, display_order = 15)]
+        top: Option<usize>,
+        /// Ignore entries occurring less than <min-count> times
+        #[structopt(long, default_value = "0", display_order = 16)]
+        min_count: u64,
+        /// Ignore entries making up less than <min-percent> percent of the analyzed passwords
+        #[structopt(long, default_value = "0", display_order = 17)]
+        min_percent: f64,
+        /// Ignore masks with a keyspace larger than <max-keyspace>
+        #[structopt(long, display_order = 18)]
+        max_keyspace: Option<u128>,
+        /// Count masks approximately using about <max-memory> MiB, the output
+        /// includes the maximum error of every count
+        #[structopt(long, display_order = 7)]
+        max_memory: Option<usize>,
+        /// Writes the combined counters to <snapshot>
+        #[structopt(long, parse(from_os_str), display_order = 8)]
+        snapshot: Option<PathBuf>,
+    },
+
+    /// Generates an optimized .hcmask from the masks written by statsgen
+    /// a summary is written to stderr
+    #[structopt(name = "maskgen")]
+    Maskgen {
+        /// Input file (statsgen mask output), stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str), display_order = 1)]
+        output: Option<PathBuf>,
+        /// Separator used in the mask input [default: TAB]
+        #[structopt(short, long, display_order = 2)]
+        separator: Option<char>,
+        /// Sort masks by occurrence, complexity or optimal (count / keyspace)
+        #[structopt(long, default_value = "optimal", display_order = 3)]
+        sort: maskgen::SortBy,
+        /// Hashes per second used to estimate the runtime
+        #[structopt(long, default_value = "1000000000", display_order = 4)]
+        pps: u64,
+        /// Maximum runtime of all selected masks in seconds
+        #[structopt(short, long, display_order = 5)]
+        target_time: Option<u64>,
+        /// Ignore masks shorter than <min-length>
+        #[structopt(long, default_value = "1", display_order = 13)]
+        min_length: usize,
+        /// Ignore masks longer than <max-length>
         #[structopt(long, default_value = "65535", display_order = 14)]
-        max_length: u16,
+        max_length: usize,
+        /// Ignore masks occurring less than <min-occurrence> times
+        #[structopt(long, default_value = "0", display_order = 15)]
+        min_occurrence: u64,
+        /// Ignore masks with a keyspace larger than <max-complexity>
+        #[structopt(long, display_order = 16)]
+        max_complexity: Option<u128>,
+        /// Print every selected mask to stderr
+        #[structopt(long, display_order = 17)]
+        show_masks: bool,
+    },
+
+    /// Counts the characters per position and the characters following every character
+    /// per position and writes them as .hcstat2 file for hashcat's markov mode to <output>
+    #[structopt(name = "markovgen")]
+    Markovgen {
+        /// Input files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Writes all counts in a human readable form to <dump>
+        #[structopt(short, long, parse(from_os_str))]
+        dump: Option<PathBuf>,
+    },
+    /// Learns a probabilistic context-free grammar (base structures like L6D2S1 and the
+    /// probabilities of their terminals) from [input] and writes it to the directory <output>
+    #[structopt(name = "pcfg-train")]
+    PcfgTrain {
+        /// Input files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
+        /// Grammar directory, created if it doesn't exist
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Generates guesses in descending probability from a grammar written by pcfg-train
+    #[structopt(name = "pcfg-guess")]
+    PcfgGuess {
+        /// Grammar directory
+        #[structopt(parse(from_os_str))]
+        grammar: PathBuf,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Stop after writing <limit> guesses
+        #[structopt(short, long)]
+        limit: Option<u64>,
+        /// Continues after the guesses of an earlier run and stores the progress in <session>
+        #[structopt(short, long, parse(from_os_str))]
+        session: Option<PathBuf>,
+    },
+    /// Reports how many lines of a wordlist every mask of an .hcmask file matches
+    /// that no earlier mask matched, the report is written to <output> and stderr
+    #[structopt(name = "maskcover")]
+    Maskcover {
+        /// .hcmask file, lines may define custom charsets
+        #[structopt(parse(from_os_str))]
+        mask_file: PathBuf,
+        /// Input files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str), display_order = 1)]
+        output: Option<PathBuf>,
+        /// Hashes per second used to estimate the runtime
+        #[structopt(long, default_value = "1000000000", display_order = 2)]
+        pps: u64,
+        /// User-defined charset ?1
+        #[structopt(short = "1", long = "custom-charset1")]
+        custom_charset1: Option<String>,
+        /// User-defined charset ?2
+        #[structopt(short = "2", long = "custom-charset2")]
+        custom_charset2: Option<String>,
+        /// User-defined charset ?3
+        #[structopt(short = "3", long = "custom-charset3")]
+        custom_charset3: Option<String>,
+        /// User-defined charset ?4
+        #[structopt(short = "4", long = "custom-charset4")]
+        custom_charset4: Option<String>,
+        /// Match UTF-8 code points instead of bytes, built-in charsets match by Unicode category
+        #[structopt(long)]
+        utf8: bool,
+    },
+
+    /// Generates all masks matching a password policy and writes them as .hcmask
+    /// a summary is written to stderr
+    #[structopt(name = "policygen")]
+    Policygen {
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str), display_order = 1)]
+        output: Option<PathBuf>,
+        /// Generate the masks NOT matching the policy
+        #[structopt(long, display_order = 2)]
+        noncompliant: bool,
+        /// Hashes per second used to estimate the runtime
+        #[structopt(long, default_value = "1000000000", display_order = 3)]
+        pps: u64,
+        /// Print every generated mask to stderr
+        #[structopt(long, display_order = 4)]
+        show_masks: bool,
+        /// Minimum password length
+        #[structopt(long, default_value = "8", display_order = 13)]
+        min_length: usize,
+        /// Maximum password length
+        #[structopt(long, default_value = "8", display_order = 14)]
+        max_length: usize,
+        /// Minimum number of digits
+        #[structopt(long, default_value = "0", display_order = 15)]
+        min_digit: usize,
+        /// Maximum number of digits
+        #[structopt(long, display_order = 16)]
+        max_digit: Option<usize>,
+        /// Minimum number of lower case characters
+        #[structopt(long, default_value = "0", display_order = 17)]
+        min_lower: usize,
+        /// Maximum number of lower case characters
+        #[structopt(long, display_order = 18)]
+        max_lower: Option<usize>,
+        /// Minimum number of upper case characters
+        #[structopt(long, default_value = "0", display_order = 19)]
+        min_upper: usize,
+        /// Maximum number of upper case characters
+        #[structopt(long, display_order = 20)]
+        max_upper: Option<usize>,
+        /// Minimum number of special characters
+        #[structopt(long, default_value = "0", display_order = 21)]
+        min_special: usize,
+        /// Maximum number of special characters
+        #[structopt(long, display_order = 22)]
+        max_special: Option<usize>,
     },
 
     /// Decodes $HEX[] encoded lines
     #[structopt(name = "unhex")]
     Unhex {
-        /// Input file, stdin if not present
+        /// Input files or directories, stdin if not present
         #[structopt(parse(from_os_str))]
-        input: Option<PathBuf>,
+        input: Vec<PathBuf>,
         /// Output file, stdout if not present
         #[structopt(short, long, parse(from_os_str))]
         output: Option<PathBuf>,
     },
+    /// Detects the encoding of every line and optionally converts them
+    #[structopt(name = "encoding")]
+    Encoding {
+        /// Input files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Encoding of lines that are neither ASCII nor UTF-8: cp1252, latin1 or cp1251
+        /// [default: detected per line]
+        #[structopt(short, long)]
+        from: Option<encoding::Codepage>,
+        /// Converts lines to this encoding: ascii, utf8, cp1252, latin1 or cp1251,
+        /// only the distribution is reported if not present
+        #[structopt(short, long)]
+        to: Option<encoding::Codepage>,
+    },
     /// Filters a wordlist by a given mask
     #[structopt(name = "filtermask")]
     Filtermask {
-        /// Mask to filter by
-        #[structopt(required(true))]
-        mask: String,
-        /// Input file, stdin if not present
+        /// Mask to filter by (hashcat syntax), omitted if --mask-file is used
+        #[structopt(required_unless = "mask-file")]
+        mask: Option<String>,
+        /// Input files or directories, stdin if not present
         #[structopt(parse(from_os_str))]
-        input: Option<PathBuf>,
+        input: Vec<PathBuf>,
+        /// Output file, stdout if not present
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// User-defined charset ?1
+        #[structopt(short = "1", long = "custom-charset1")]
+        custom_charset1: Option<String>,
+        /// User-defined charset ?2
+        #[structopt(short = "2", long = "custom-charset2")]
+        custom_charset2: Option<String>,
+        /// User-defined charset ?3
+        #[structopt(short = "3", long = "custom-charset3")]
+        custom_charset3: Option<String>,
+        /// User-defined charset ?4
+        #[structopt(short = "4", long = "custom-charset4")]
+        custom_charset4: Option<String>,
+        /// Filters by all masks of an .hcmask file, lines may define custom charsets
+        #[structopt(short, long, parse(from_os_str))]
+        mask_file: Option<PathBuf>,
+        /// Writes the lines not matching any mask
+        #[structopt(short = "v", long, conflicts_with = "tag")]
+        invert: bool,
+        /// Appends the matching mask to every line, separated by TAB
+        #[structopt(short, long)]
+        tag: bool,
+        /// Match UTF-8 code points instead of bytes, built-in charsets match by Unicode category
+        #[structopt(long)]
+        utf8: bool,
+    },
+    /// Counts the frequency of every byte overall, at the first and last position and
+    /// at every position from the start, the table is written to <output>
+    #[structopt(name = "charfreq")]
+    Charfreq {
+        /// Input files or directories, stdin if not present
+        #[structopt(parse(from_os_str))]
+        input: Vec<PathBuf>,
         /// Output file, stdout if not present
         #[structopt(short, long, parse(from_os_str))]
         output: Option<PathBuf>,
+        /// Number of positions counted from the start of the line
+        #[structopt(short, long, default_value = "16")]
+        positions: usize,
+        /// Number of characters written to every .hcchr file
+        #[structopt(short, long, default_value = "10")]
+        top: usize,
+        /// Writes a .hcchr custom charset of the top characters of every position to <hcchr>
+        #[structopt(long, parse(from_os_str))]
+        hcchr: Option<PathBuf>,
     },
     /// Splits each line on the charset boundry
     #[structopt(name = "cgrams")]
     Cgrams {
-        /// Input file, stdin if not present
+        /// Input files or directories, stdin if not present
         #[structopt(parse(from_os_str))]
-        input: Option<PathBuf>,
+        input: Vec<PathBuf>,
         /// Output file, stdout if not present
         #[structopt(short, long, parse(from_os_str))]
         output: Option<PathBuf>,
@@ -72,6 +382,9 @@ pub enum CmdOpts {
         /// Normalizes "Hello" to "hello", both variants will be used
         #[structopt(short, long)]
         normalize: bool,
+        /// Split on UTF-8 code points classified by their Unicode category
+        #[structopt(long)]
+        utf8: bool,
     },
 }
 
@@ -84,18 +397,241 @@ fn main() {
             separator,
             min_length,
             max_length,
+            efficiency,
+            format,
+            threads,
+            utf8,
+            top,
+            min_count,
+            min_percent,
+            max_keyspace,
+            max_memory,
+            snapshot,
+            simple_mask_segments,
+            simple_mask_case,
+            fold_binary,
+        } => {
+            let threads = threads.unwrap_or_else(num_cpus::get).max(1);
+            let options = statsgen::Options {
+                min_length,
+                max_length: max_length.unwrap_or(usize::MAX),
+                efficiency,
+                utf8,
+                simple_mask: statsgen::SimpleMaskOptions {
+                    max_segments: simple_mask_segments,
+                    case: simple_mask_case,
+                    fold_binary,
+                },
+                top,
+                min_count,
+                min_percent,
+                max_keyspace,
+                // shared by the stats of every worker thread and the merged ones
+                max_memory: cli::statsgen::memory_per_stats(max_memory, threads + 1),
+            };
+            let cli_options = cli::statsgen::CliOptions {
+                format,
+                separator,
+                threads,
+                snapshot,
+            };
+            cli::statsgen::gen(input, output, &options, &cli_options);
+        }
+        CmdOpts::Statsmerge {
+            input,
+            output,
+            separator,
+            efficiency,
+            format,
+            top,
+            min_count,
+            min_percent,
+            max_keyspace,
+            max_memory,
+            snapshot,
+        } => {
+            // the counters are already collected, only the report options apply
+            let options = statsgen::Options {
+                min_length: 1,
+                max_length: usize::MAX,
+                efficiency,
+                utf8: false,
+                simple_mask: statsgen::SimpleMaskOptions::default(),
+                top,
+                min_count,
+                min_percent,
+                max_keyspace,
+                // shared by the merged stats and the snapshot read
+                max_memory: cli::statsgen::memory_per_stats(max_memory, 2),
+            };
+            let cli_options = cli::statsgen::CliOptions {
+                format,
+                separator,
+                threads: 1,
+                snapshot,
+            };
+            cli::statsgen::merge_snapshots(input, output, &options, &cli_options);
+        }
+        CmdOpts::Maskgen {
+            input,
+            output,
+            separator,
+            sort,
+            pps,
+            target_time,
+            min_length,
+            max_length,
+            min_occurrence,
+            max_complexity,
+            show_masks,
+        } => {
+            if pps == 0 {
+                eprintln!("--pps must be greater than 0");
+                process::exit(-1);
+            }
+            let options = maskgen::Options {
+                sort,
+                pps,
+                target_time,
+                min_length,
+                max_length,
+                min_occurrence,
+                max_complexity,
+                show_masks,
+            };
+            maskgen::maskgen(input, output, separator, &options);
+        }
+        CmdOpts::Markovgen {
+            input,
+            output,
+            dump,
+        } => {
+            markovgen::markovgen(input, output, dump);
+        }
+        CmdOpts::PcfgTrain { input, output } => {
+            pcfg::train(input, output);
+        }
+        CmdOpts::PcfgGuess {
+            grammar,
+            output,
+            limit,
+            session,
+        } => {
+            pcfg::guess(grammar, output, limit, session);
+        }
+        CmdOpts::Maskcover {
+            mask_file,
+            input,
+            output,
+            pps,
+            custom_charset1,
+            custom_charset2,
+            custom_charset3,
+            custom_charset4,
+            utf8,
+        } => {
+            if pps == 0 {
+                eprintln!("--pps must be greater than 0");
+                process::exit(-1);
+            }
+            let custom_charsets = [
+                custom_charset1,
+                custom_charset2,
+                custom_charset3,
+                custom_charset4,
+            ];
+            maskcover::maskcover(mask_file, input, output, custom_charsets, pps, utf8);
+        }
+        CmdOpts::Policygen {
+            output,
+            noncompliant,
+            pps,
+            show_masks,
+            min_length,
+            max_length,
+            min_digit,
+            max_digit,
+            min_lower,
+            max_lower,
+            min_upper,
+            max_upper,
+            min_special,
+            max_special,
         } => {
-            statsgen::gen(input, output, separator, min_length, max_length);
+            if min_length == 0 || min_length > max_length {
+                eprintln!("--min-length must be between 1 and --max-length");
+                process::exit(-1);
+            }
+            if pps == 0 {
+                eprintln!("--pps must be greater than 0");
+                process::exit(-1);
+            }
+            let policy = policygen::Policy {
+                min_length,
+                max_length,
+                min_digit,
+                max_digit,
+                min_lower,
+                max_lower,
+                min_upper,
+                max_upper,
+                min_special,
+                max_special,
+            };
+            policygen::policygen(output, &policy, noncompliant, pps, show_masks);
         }
         CmdOpts::Unhex { input, output } => {
             unhex::unhex(input, output);
         }
+        CmdOpts::Encoding {
+            input,
+            output,
+            from,
+            to,
+        } => {
+            encoding::encoding(input, output, from, to);
+        }
         CmdOpts::Filtermask {
+            mut input,
+            output,
+            mut mask,
+            custom_charset1,
+            custom_charset2,
+            custom_charset3,
+            custom_charset4,
+            mask_file,
+            invert,
+            tag,
+            utf8,
+        } => {
+            // with --mask-file the first positional argument is an input file
+            if mask_file.is_some() {
+                if let Some(mask) = mask.take() {
+                    input.insert(0, PathBuf::from(mask));
+                }
+            }
+            let custom_charsets = [
+                custom_charset1,
+                custom_charset2,
+                custom_charset3,
+                custom_charset4,
+            ];
+            let options = filtermask::Options { invert, tag, utf8 };
+            cli::filtermask::filtermask(input, output, mask, mask_file, custom_charsets, &options);
+        }
+        CmdOpts::Charfreq {
             input,
             output,
-            mask,
+            positions,
+            top,
+            hcchr,
         } => {
-            filtermask::filtermask(input, output, mask);
+            let options = charfreq::Options {
+                positions,
+                top,
+                hcchr,
+            };
+            charfreq::charfreq(input, output, &options);
         }
         CmdOpts::Cgrams {
             input,
@@ -103,12 +639,13 @@ fn main() {
             sort,
             ignore_case,
             normalize,
+            utf8,
         } => {
             if !ignore_case && normalize {
                 eprintln!("--normalize only works together with --ignore-case");
                 process::exit(-1);
             }
-            cgrams::gen_c_grams(input, output, sort, ignore_case, normalize);
+            cli::cgrams::gen_c_grams(input, output, sort, ignore_case, normalize, utf8);
         }
     }
 }
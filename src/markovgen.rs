@@ -0,0 +1,153 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+use pack2_util::lzma2::Lzma2Encoder;
+use pack2_util::*;
+
+/// Signature of the hcstat2 format, "hcstat" followed by the version 3
+const HCSTAT2_VERSION: u64 = 0x6863_7374_6174_0003;
+/// Number of positions of the hcstat2 tables, longer lines are skipped
+const MAX_LENGTH: usize = 256;
+const CHARSIZ: usize = 256;
+const ROOT_CNT: usize = MAX_LENGTH * CHARSIZ;
+const MARKOV_CNT: usize = MAX_LENGTH * CHARSIZ * CHARSIZ;
+
+/// Character counts per position (`root`) and counts of every character following
+/// another one per position (`markov`), laid out like the tables of hashcat
+struct MarkovStats {
+    root: Vec<u64>,
+    markov: Vec<u64>,
+    processed: u64,
+    skipped: u64,
+}
+
+impl MarkovStats {
+    fn new() -> MarkovStats {
+        MarkovStats {
+            root: vec![0; ROOT_CNT],
+            markov: vec![0; MARKOV_CNT],
+            processed: 0,
+            skipped: 0,
+        }
+    }
+
+    fn add_line(&mut self, line: &[u8]) {
+        if line.is_empty() || line.len() > MAX_LENGTH {
+            self.skipped += 1;
+            return;
+        }
+        self.processed += 1;
+        for (pos, c) in line.iter().enumerate() {
+            self.root[pos * CHARSIZ + *c as usize] += 1;
+        }
+        for (pos, pair) in line.windows(2).enumerate() {
+            self.markov[(pos * CHARSIZ + pair[0] as usize) * CHARSIZ + pair[1] as usize] += 1;
+        }
+    }
+
+    /// Writes the tables in the hcstat2 format: the big endian signature, a zero
+    /// and both tables as big endian u64, compressed as raw LZMA2 stream
+    fn write_hcstat2<W: Write>(&self, writer: W) {
+        let mut encoder = match Lzma2Encoder::new(writer, 6) {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(-1);
+            }
+        };
+        let header = [HCSTAT2_VERSION, 0];
+        let mut buf: Vec<u8> = Vec::new();
+        for chunk in header
+            .chunks(CHARSIZ)
+            .chain(self.root.chunks(CHARSIZ))
+            .chain(self.markov.chunks(CHARSIZ))
+        {
+            buf.clear();
+            for value in chunk {
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+            if let Err(e) = encoder.write_all(&buf) {
+                eprintln!("{}", e);
+                process::exit(-1);
+            }
+        }
+        if let Err(e) = encoder.finish() {
+            eprintln!("{}", e);
+            process::exit(-1);
+        }
+    }
+
+    /// Writes every non-zero count, most frequent first per position and previous character
+    fn write_dump(&self, writer: &mut Box<dyn Write>) {
+        let mut out: Vec<u8> = Vec::new();
+        let mut prev_out: Vec<u8> = Vec::new();
+        for pos in 0..MAX_LENGTH {
+            let counts = &self.root[pos * CHARSIZ..(pos + 1) * CHARSIZ];
+            for (c, count) in sorted_counts(counts) {
+                encode_hex_if_needed(vec![c], &mut out);
+                let line = format!(
+                    "root\t{}\t{}\t{}\n",
+                    pos + 1,
+                    String::from_utf8_lossy(&out),
+                    count
+                );
+                mywrite(&mut line.as_bytes(), writer);
+            }
+        }
+        for pos in 0..MAX_LENGTH - 1 {
+            for prev in 0..CHARSIZ {
+                let start = (pos * CHARSIZ + prev) * CHARSIZ;
+                let counts = &self.markov[start..start + CHARSIZ];
+                for (c, count) in sorted_counts(counts) {
+                    encode_hex_if_needed(vec![prev as u8], &mut prev_out);
+                    encode_hex_if_needed(vec![c], &mut out);
+                    let line = format!(
+                        "markov\t{}\t{}\t{}\t{}\n",
+                        pos + 1,
+                        String::from_utf8_lossy(&prev_out),
+                        String::from_utf8_lossy(&out),
+                        count
+                    );
+                    mywrite(&mut line.as_bytes(), writer);
+                }
+            }
+        }
+    }
+}
+
+/// Non-zero counts of a table row, most frequent first
+fn sorted_counts(counts: &[u64]) -> Vec<(u8, u64)> {
+    let mut sorted: Vec<(u8, u64)> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(c, count)| (c as u8, *count))
+        .collect();
+    sorted.sort_by(|(a_c, a), (b_c, b)| b.cmp(a).then_with(|| a_c.cmp(b_c)));
+    sorted
+}
+
+pub fn markovgen(input: Vec<PathBuf>, output: Option<PathBuf>, dump: Option<PathBuf>) {
+    let mut stats = MarkovStats::new();
+    let mut hex_buf: Vec<u8> = Vec::new();
+
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        stats.add_line(line);
+    });
+
+    // empty lines and lines longer than the hcstat2 tables are skipped
+    eprintln!(
+        "[+] Analyzed {} / {} passwords.",
+        stats.processed,
+        stats.processed + stats.skipped
+    );
+
+    stats.write_hcstat2(get_writer(output));
+
+    if let Some(dump) = dump {
+        let mut writer = get_writer(Some(dump));
+        stats.write_dump(&mut writer);
+    }
+}
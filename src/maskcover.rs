@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use std::process;
+
+use pack2_util::mask::CustomCharsets;
+use pack2_util::*;
+
+pub fn maskcover(
+    mask_file: PathBuf,
+    input: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    custom_charsets: [Option<String>; 4],
+    pps: u64,
+    utf8: bool,
+) {
+    let custom = match CustomCharsets::parse(&custom_charsets) {
+        Ok(custom) => custom,
+        Err(e) => {
+            eprintln!("invalid custom charset: {}", e);
+            process::exit(-1);
+        }
+    };
+    let masks = read_hcmask(mask_file, &custom);
+    if masks.is_empty() {
+        eprintln!("no masks found in mask file");
+        process::exit(-1);
+    }
+
+    // every line is attributed to the first mask matching it
+    let mut new_matches = vec![0u64; masks.len()];
+    let mut total_lines: u64 = 0;
+    let mut hex_buf: Vec<u8> = Vec::new();
+
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        total_lines += 1;
+        let matched = match utf8 {
+            false => masks.first_match(line),
+            true => masks.first_match_utf8(line),
+        };
+        if let Some(idx) = matched {
+            new_matches[idx] += 1;
+        }
+    });
+
+    let mut writer = get_writer(output);
+    let mut cumulative: u64 = 0;
+    let mut cumulative_keyspace: u128 = 0;
+
+    eprintln!(
+        "[*] Masks:                         new    cumulative  coverage             keyspace      runtime"
+    );
+    for (idx, count) in new_matches.iter().enumerate() {
+        cumulative += count;
+        cumulative_keyspace = cumulative_keyspace.saturating_add(masks.mask(idx).keyspace());
+        let coverage = 100.0 / total_lines as f64 * cumulative as f64;
+        let name = String::from_utf8_lossy(masks.name(idx));
+
+        eprintln!(
+            "[+] {: >26}: {: >12} {: >12} {: >8.2}% {: >20} {: >12}",
+            name,
+            count,
+            cumulative,
+            coverage,
+            cumulative_keyspace,
+            format_runtime(cumulative_keyspace / pps as u128)
+        );
+        let out = format!(
+            "{}\t{}\t{}\t{:.4}\t{}\n",
+            name, count, cumulative, coverage, cumulative_keyspace
+        );
+        mywrite(&mut out.as_bytes(), &mut writer);
+    }
+
+    eprintln!(
+        "[*] Coverage: {:.2}% ({} / {} lines) with {} masks.",
+        100.0 / total_lines as f64 * cumulative as f64,
+        cumulative,
+        total_lines,
+        masks.len()
+    );
+    eprintln!(
+        "[*] Runtime: {} (keyspace: {} at {} h/s)",
+        format_runtime(cumulative_keyspace / pps as u128),
+        cumulative_keyspace,
+        pps
+    );
+}
@@ -0,0 +1,189 @@
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use bstr::{io::BufReadExt, ByteSlice};
+use pack2_util::mask::{CustomCharsets, Mask};
+use pack2_util::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// Most frequent masks first
+    Occurrence,
+    /// Smallest keyspace first
+    Complexity,
+    /// Highest count / keyspace ratio first
+    Optimal,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SortBy, String> {
+        match s {
+            "occurrence" => Ok(SortBy::Occurrence),
+            "complexity" => Ok(SortBy::Complexity),
+            "optimal" => Ok(SortBy::Optimal),
+            _ => Err(format!(
+                "unknown sort order '{}', use occurrence, complexity or optimal",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub sort: SortBy,
+    /// Hashes per second used to estimate the runtime
+    pub pps: u64,
+    /// Stop adding masks once their combined runtime exceeds this many seconds
+    pub target_time: Option<u64>,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub min_occurrence: u64,
+    pub max_complexity: Option<u128>,
+    pub show_masks: bool,
+}
+
+struct MaskEntry {
+    mask: Mask,
+    count: u64,
+    keyspace: u128,
+}
+
+impl MaskEntry {
+    fn ratio(&self) -> f64 {
+        self.count as f64 / self.keyspace as f64
+    }
+}
+
+/// Reads masks as written by statsgen: mask, percent, count and optional extra columns
+fn read_masks(input: Option<PathBuf>, separator: char) -> Vec<MaskEntry> {
+    let name = input_name(&input);
+    let reader = get_reader(input);
+    let custom = CustomCharsets::new();
+    let mut separator_buf = [0u8; 4];
+    let separator = separator.encode_utf8(&mut separator_buf).as_bytes();
+
+    let mut masks = Vec::new();
+    for (line_num, result) in reader.byte_lines().enumerate() {
+        let line = match result {
+            Ok(line) => line,
+            Err(e) => read_error(&name, e),
+        };
+        let mut columns = line.split_str(separator);
+        let mask = columns.next().unwrap_or_default();
+        let count = columns
+            .nth(1)
+            .and_then(|count| count.to_str().ok())
+            .and_then(|count| count.parse::<u64>().ok());
+
+        let (mask, count) = match (Mask::parse(mask, &custom), count) {
+            (Ok(mask), Some(count)) => (mask, count),
+            _ => {
+                eprintln!(
+                    "[!] skipping invalid line {}: {}",
+                    line_num + 1,
+                    line.as_bstr()
+                );
+                continue;
+            }
+        };
+        let keyspace = mask.keyspace();
+        masks.push(MaskEntry {
+            mask,
+            count,
+            keyspace,
+        });
+    }
+    masks
+}
+
+pub fn maskgen(
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    separator: Option<char>,
+    options: &Options,
+) {
+    let separator: char = separator.unwrap_or('\t');
+    let mut masks = read_masks(input, separator);
+    if masks.is_empty() {
+        eprintln!("no masks found in input");
+        process::exit(-1);
+    }
+
+    let total_masks = masks.len();
+    let total_count: u64 = masks.iter().map(|entry| entry.count).sum();
+
+    match options.sort {
+        SortBy::Occurrence => masks.sort_by_key(|entry| Reverse(entry.count)),
+        SortBy::Complexity => masks.sort_by_key(|entry| entry.keyspace),
+        SortBy::Optimal => masks.sort_by(|a, b| b.ratio().partial_cmp(&a.ratio()).unwrap()),
+    }
+
+    let mut writer = get_writer(output);
+
+    let mut selected_masks = 0;
+    let mut selected_count: u64 = 0;
+    let mut selected_keyspace: u128 = 0;
+
+    if options.show_masks {
+        eprintln!(
+            "[*] Selected masks:                           count             keyspace      runtime"
+        );
+    }
+
+    for entry in masks {
+        let mask_len = entry.mask.len();
+        if mask_len < options.min_length
+            || mask_len > options.max_length
+            || entry.count < options.min_occurrence
+        {
+            continue;
+        }
+        if let Some(max_complexity) = options.max_complexity {
+            if entry.keyspace > max_complexity {
+                continue;
+            }
+        }
+
+        let keyspace = selected_keyspace.saturating_add(entry.keyspace);
+        if let Some(target_time) = options.target_time {
+            if keyspace / options.pps as u128 > target_time as u128 {
+                break;
+            }
+        }
+        selected_keyspace = keyspace;
+        selected_masks += 1;
+        selected_count += entry.count;
+
+        if options.show_masks {
+            eprintln!(
+                "[+] {: >26}: {: >18} {: >20} {: >12}",
+                entry.mask,
+                entry.count,
+                entry.keyspace,
+                format_runtime(entry.keyspace / options.pps as u128)
+            );
+        }
+
+        let mut out = entry.mask.to_bytes();
+        out.push(b'\n');
+        mywrite(&mut out.as_slice(), &mut writer);
+    }
+
+    let coverage = 100.0 / total_count as f64 * selected_count as f64;
+    eprintln!("[*] Selected {} / {} masks.", selected_masks, total_masks);
+    eprintln!(
+        "[*] Coverage: {:.2}% ({} / {})",
+        coverage, selected_count, total_count
+    );
+    eprintln!(
+        "[*] Runtime: {} (keyspace: {} at {} h/s)",
+        format_runtime(selected_keyspace / options.pps as u128),
+        selected_keyspace,
+        options.pps
+    );
+}
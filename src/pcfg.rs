@@ -0,0 +1,519 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use hashbrown::HashMap;
+use pack2_util::*;
+use serde::{Deserialize, Serialize};
+
+/// Segment types of a base structure, a line is split wherever the
+/// simple mask class (`CHAR2SMASK`) changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Alpha,
+    Digit,
+    Special,
+    Other,
+}
+
+impl Segment {
+    fn from_symbol(symbol: u8) -> Option<Segment> {
+        match symbol {
+            b'L' => Some(Segment::Alpha),
+            b'D' => Some(Segment::Digit),
+            b'S' => Some(Segment::Special),
+            b'O' => Some(Segment::Other),
+            _ => None,
+        }
+    }
+
+    fn from_smask(smask: u8) -> Segment {
+        match smask {
+            1 => Segment::Alpha,
+            4 => Segment::Digit,
+            8 => Segment::Special,
+            _ => Segment::Other,
+        }
+    }
+
+    /// Letter used in base structures, e.g. `L6D2S1`
+    pub fn symbol(self) -> char {
+        match self {
+            Segment::Alpha => 'L',
+            Segment::Digit => 'D',
+            Segment::Special => 'S',
+            Segment::Other => 'O',
+        }
+    }
+
+    /// Directory of the terminals of the segment type
+    pub fn dir(self) -> &'static str {
+        match self {
+            Segment::Alpha => "alpha",
+            Segment::Digit => "digits",
+            Segment::Special => "specials",
+            Segment::Other => "other",
+        }
+    }
+}
+
+/// Directory of the capitalization masks of alpha segments
+pub const CAPITALIZATION_DIR: &str = "capitalization";
+/// File of the base structures in the grammar directory
+pub const STRUCTURES_FILE: &str = "structures.txt";
+
+/// Occurrences per terminal or base structure
+type Counts = HashMap<Vec<u8>, u64>;
+
+/// Counts learned from a wordlist
+#[derive(Default)]
+struct Training {
+    processed: u64,
+    structures: Counts,
+    /// Terminals per directory and length
+    terminals: HashMap<(&'static str, usize), Counts>,
+}
+
+impl Training {
+    fn add_line(&mut self, line: &[u8]) {
+        if line.is_empty() {
+            return;
+        }
+        self.processed += 1;
+
+        let mut structure: Vec<u8> = Vec::new();
+        let mut start = 0;
+        while start < line.len() {
+            let smask = CHAR2SMASK[line[start] as usize];
+            let mut end = start + 1;
+            while end < line.len() && CHAR2SMASK[line[end] as usize] == smask {
+                end += 1;
+            }
+            let segment = Segment::from_smask(smask);
+            let terminal = &line[start..end];
+            let len = terminal.len();
+            structure.extend_from_slice(format!("{}{}", segment.symbol(), len).as_bytes());
+
+            if segment == Segment::Alpha {
+                // words are stored lowercase, their case is learned separately
+                let capitalization: Vec<u8> = terminal
+                    .iter()
+                    .map(|c| if c.is_ascii_uppercase() { b'U' } else { b'L' })
+                    .collect();
+                self.add_terminal(CAPITALIZATION_DIR, capitalization);
+                self.add_terminal(segment.dir(), terminal.to_ascii_lowercase());
+            } else {
+                self.add_terminal(segment.dir(), terminal.to_vec());
+            }
+            start = end;
+        }
+        *self.structures.entry(structure).or_insert(0) += 1;
+    }
+
+    fn add_terminal(&mut self, dir: &'static str, terminal: Vec<u8>) {
+        let len = terminal.len();
+        *self
+            .terminals
+            .entry((dir, len))
+            .or_default()
+            .entry(terminal)
+            .or_insert(0) += 1;
+    }
+}
+
+/// Sorts by count (descending) and terminal and converts the counts to probabilities
+fn probabilities(counts: Counts) -> Vec<(Vec<u8>, u64, f64)> {
+    let total: u64 = counts.values().sum();
+    let mut entries: Vec<(Vec<u8>, u64, f64)> = counts
+        .into_iter()
+        .map(|(terminal, count)| (terminal, count, count as f64 / total as f64))
+        .collect();
+    entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries
+}
+
+/// Writes one `terminal\tprobability` line per entry, most probable first
+fn write_table(path: &Path, entries: &[(Vec<u8>, u64, f64)]) {
+    let mut writer = get_writer(Some(path.to_path_buf()));
+    let mut out: Vec<u8> = Vec::new();
+    for (terminal, _, probability) in entries {
+        encode_hex_if_needed(terminal.to_vec(), &mut out);
+        out.extend_from_slice(format!("\t{}\n", probability).as_bytes());
+        mywrite(&mut out.as_slice(), &mut writer);
+    }
+}
+
+fn create_dir(path: &Path) {
+    if let Err(e) = fs::create_dir_all(path) {
+        eprintln!("can't create '{}': {}", path.display(), e);
+        process::exit(-1);
+    }
+}
+
+/// Learns a PCFG from the input and writes it to the grammar directory <output>:
+/// `structures.txt` with the base structures and one file per length (`<N>.txt`)
+/// in `alpha`, `capitalization`, `digits`, `specials` and `other`
+pub fn train(input: Vec<PathBuf>, output: PathBuf) {
+    // stale tables of an earlier training would end up in the grammar otherwise
+    if let Ok(mut entries) = fs::read_dir(&output) {
+        if entries.next().is_some() {
+            eprintln!("grammar directory '{}' is not empty", output.display());
+            process::exit(-1);
+        }
+    }
+
+    let mut training = Training::default();
+    let mut total: u64 = 0;
+    let mut hex_buf: Vec<u8> = Vec::new();
+
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        total += 1;
+        training.add_line(line);
+    });
+
+    eprintln!("[+] Analyzed {} / {} passwords.", training.processed, total);
+
+    create_dir(&output);
+    let structures = probabilities(training.structures);
+    eprintln!("[*] Base structures: {}", structures.len());
+    for (structure, count, probability) in structures.iter().take(25) {
+        eprintln!(
+            "[+] {: >26}: {: >6.2}% ({})",
+            String::from_utf8_lossy(structure),
+            probability * 100.0,
+            count
+        );
+    }
+    write_table(&output.join(STRUCTURES_FILE), &structures);
+
+    let mut tables: Vec<((&str, usize), Counts)> = training.terminals.into_iter().collect();
+    tables.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    eprintln!("[*] Terminals:");
+    for ((dir, len), counts) in tables {
+        let entries = probabilities(counts);
+        eprintln!("[+] {: >26}: {}", format!("{}/{}", dir, len), entries.len());
+        let dir = output.join(dir);
+        create_dir(&dir);
+        write_table(&dir.join(format!("{}.txt", len)), &entries);
+    }
+}
+
+/// Terminals of one table with their probabilities, most probable first
+type Table = Vec<(Vec<u8>, f64)>;
+
+/// Base structure with the tables its terminals are taken from. An alpha
+/// segment uses two slots, the lowercase word and its capitalization.
+struct Structure {
+    probability: f64,
+    segments: Vec<Segment>,
+    slots: Vec<usize>,
+}
+
+struct Grammar {
+    structures: Vec<Structure>,
+    tables: Vec<Table>,
+}
+
+fn read_table(path: PathBuf) -> Table {
+    let name = path.display().to_string();
+    let mut table = Table::new();
+    for_each_line(vec![path], |line| {
+        if line.is_empty() {
+            return;
+        }
+        let parsed = line.iter().rposition(|c| *c == b'\t').and_then(|tab| {
+            let probability = std::str::from_utf8(&line[tab + 1..]).ok()?.parse().ok()?;
+            Some((decode_hex_if_needed(line[..tab].to_vec()).0, probability))
+        });
+        match parsed {
+            Some(entry) => table.push(entry),
+            None => {
+                eprintln!(
+                    "invalid line in '{}': {}",
+                    name,
+                    String::from_utf8_lossy(line)
+                );
+                process::exit(-1);
+            }
+        }
+    });
+    table
+}
+
+/// Parses a base structure like `L4D2S1`
+fn parse_structure(structure: &[u8]) -> Option<Vec<(Segment, usize)>> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    while pos < structure.len() {
+        let segment = Segment::from_symbol(structure[pos])?;
+        let digits = structure[pos + 1..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        let len = std::str::from_utf8(&structure[pos + 1..pos + 1 + digits])
+            .ok()?
+            .parse()
+            .ok()?;
+        segments.push((segment, len));
+        pos += 1 + digits;
+    }
+    Some(segments)
+}
+
+impl Grammar {
+    /// Reads the grammar directory written by `train`, only the tables used by
+    /// a base structure are loaded
+    fn read(dir: &Path) -> Grammar {
+        let mut grammar = Grammar {
+            structures: Vec::new(),
+            tables: Vec::new(),
+        };
+        let mut table_ids: HashMap<(&'static str, usize), usize> = HashMap::new();
+
+        for (structure, probability) in read_table(dir.join(STRUCTURES_FILE)) {
+            let parsed = match parse_structure(&structure) {
+                Some(parsed) => parsed,
+                None => {
+                    eprintln!(
+                        "invalid base structure '{}'",
+                        String::from_utf8_lossy(&structure)
+                    );
+                    process::exit(-1);
+                }
+            };
+            let mut slots = Vec::new();
+            for (segment, len) in &parsed {
+                let mut dirs = vec![segment.dir()];
+                if *segment == Segment::Alpha {
+                    dirs.push(CAPITALIZATION_DIR);
+                }
+                for table_dir in dirs {
+                    let tables = &mut grammar.tables;
+                    let id = *table_ids.entry((table_dir, *len)).or_insert_with(|| {
+                        let path = dir.join(table_dir).join(format!("{}.txt", len));
+                        tables.push(read_table(path));
+                        tables.len() - 1
+                    });
+                    slots.push(id);
+                }
+            }
+            grammar.structures.push(Structure {
+                probability,
+                segments: parsed.into_iter().map(|(segment, _)| segment).collect(),
+                slots,
+            });
+        }
+        grammar
+    }
+
+    fn probability(&self, structure: usize, indices: &[usize]) -> f64 {
+        let structure = &self.structures[structure];
+        structure
+            .slots
+            .iter()
+            .zip(indices)
+            .map(|(table, idx)| self.tables[*table][*idx].1)
+            .product::<f64>()
+            * structure.probability
+    }
+
+    /// Writes the guess of a pre-terminal to `out`
+    fn guess(&self, structure: usize, indices: &[usize], out: &mut Vec<u8>) {
+        let structure = &self.structures[structure];
+        let mut terminals = structure
+            .slots
+            .iter()
+            .zip(indices)
+            .map(|(table, idx)| &self.tables[*table][*idx].0);
+        out.clear();
+        for segment in &structure.segments {
+            let terminal = terminals.next().unwrap();
+            if *segment == Segment::Alpha {
+                let capitalization = terminals.next().unwrap();
+                out.extend(
+                    terminal
+                        .iter()
+                        .zip(capitalization)
+                        .map(|(c, case)| match case {
+                            b'U' => c.to_ascii_uppercase(),
+                            _ => *c,
+                        }),
+                );
+            } else {
+                out.extend_from_slice(terminal);
+            }
+        }
+    }
+}
+
+/// Base structure with one terminal chosen per slot. Children only advance the
+/// slots from `pivot` on, so every pre-terminal is reached exactly once.
+struct PreTerminal {
+    probability: f64,
+    structure: usize,
+    pivot: usize,
+    indices: Vec<usize>,
+}
+
+impl PartialEq for PreTerminal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PreTerminal {}
+
+impl PartialOrd for PreTerminal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PreTerminal {
+    /// Most probable first, ties are broken by structure and terminals to keep the
+    /// order deterministic, which resuming a session relies on
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.probability
+            .total_cmp(&other.probability)
+            .then_with(|| other.structure.cmp(&self.structure))
+            .then_with(|| other.indices.cmp(&self.indices))
+    }
+}
+
+/// Number of guesses between two updates of the session file
+const SESSION_INTERVAL: u64 = 1_000_000;
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+    grammar: PathBuf,
+    guesses: u64,
+}
+
+fn read_session(path: &Path, grammar: &Path) -> u64 {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(_) => return 0,
+    };
+    let session: Session = match serde_json::from_slice(&data) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("invalid session '{}': {}", path.display(), e);
+            process::exit(-1);
+        }
+    };
+    if session.grammar != grammar {
+        eprintln!(
+            "session '{}' belongs to grammar '{}'",
+            path.display(),
+            session.grammar.display()
+        );
+        process::exit(-1);
+    }
+    session.guesses
+}
+
+fn write_session(path: &Path, grammar: &Path, guesses: u64) {
+    let session = Session {
+        grammar: grammar.to_path_buf(),
+        guesses,
+    };
+    let mut out = serde_json::to_vec(&session).unwrap();
+    out.push(b'\n');
+    if let Err(e) = fs::write(path, out) {
+        eprintln!("can't write session '{}': {}", path.display(), e);
+        process::exit(-1);
+    }
+}
+
+/// Generates the guesses of the grammar in <grammar> in descending probability.
+/// With a session the guesses written by earlier runs are skipped and the session
+/// is updated every `SESSION_INTERVAL` guesses and at the end.
+pub fn guess(
+    grammar_dir: PathBuf,
+    output: Option<PathBuf>,
+    limit: Option<u64>,
+    session: Option<PathBuf>,
+) {
+    let grammar = Grammar::read(&grammar_dir);
+    let grammar_dir = fs::canonicalize(&grammar_dir).unwrap_or(grammar_dir);
+    let skip = match &session {
+        Some(session) => read_session(session, &grammar_dir),
+        None => 0,
+    };
+
+    let mut queue: BinaryHeap<PreTerminal> = BinaryHeap::new();
+    for (idx, structure) in grammar.structures.iter().enumerate() {
+        if structure
+            .slots
+            .iter()
+            .any(|t| grammar.tables[*t].is_empty())
+        {
+            continue;
+        }
+        let indices = vec![0; structure.slots.len()];
+        queue.push(PreTerminal {
+            probability: grammar.probability(idx, &indices),
+            structure: idx,
+            pivot: 0,
+            indices,
+        });
+    }
+
+    let mut writer = get_writer(output);
+    let mut guesses: u64 = 0;
+    let mut written: u64 = 0;
+    let mut last_probability = 0.0;
+    let mut out: Vec<u8> = Vec::new();
+
+    while let Some(node) = queue.pop() {
+        if limit.is_some_and(|limit| written >= limit) {
+            break;
+        }
+
+        let slots = &grammar.structures[node.structure].slots;
+        for pivot in node.pivot..slots.len() {
+            if node.indices[pivot] + 1 < grammar.tables[slots[pivot]].len() {
+                let mut indices = node.indices.clone();
+                indices[pivot] += 1;
+                queue.push(PreTerminal {
+                    probability: grammar.probability(node.structure, &indices),
+                    structure: node.structure,
+                    pivot,
+                    indices,
+                });
+            }
+        }
+
+        if guesses >= skip {
+            grammar.guess(node.structure, &node.indices, &mut out);
+            encode_hex_if_needed(out.clone(), &mut out);
+            out.push(b'\n');
+            mywrite(&mut out.as_slice(), &mut writer);
+            written += 1;
+            last_probability = node.probability;
+        }
+        guesses += 1;
+
+        if let Some(session) = &session {
+            if guesses.is_multiple_of(SESSION_INTERVAL) && guesses > skip {
+                writer.flush().unwrap();
+                write_session(session, &grammar_dir, guesses);
+            }
+        }
+    }
+
+    writer.flush().unwrap();
+    if let Some(session) = &session {
+        write_session(session, &grammar_dir, guesses.max(skip));
+    }
+    eprintln!(
+        "[*] Wrote {} guesses ({} in total), last probability: {:e}",
+        written,
+        guesses.max(skip),
+        last_probability
+    );
+}
@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use pack2_util::mask::Mask;
+use pack2_util::*;
+
+/// Built-in charsets in the order they are enumerated
+const CLASSES: [u8; 4] = [b'd', b'l', b'u', b's'];
+
+#[derive(Debug)]
+pub struct Policy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub min_digit: usize,
+    pub max_digit: Option<usize>,
+    pub min_lower: usize,
+    pub max_lower: Option<usize>,
+    pub min_upper: usize,
+    pub max_upper: Option<usize>,
+    pub min_special: usize,
+    pub max_special: Option<usize>,
+}
+
+impl Policy {
+    fn complies(&self, counts: &[usize; 4]) -> bool {
+        let within = |count: usize, min: usize, max: Option<usize>| {
+            count >= min && count <= max.unwrap_or(usize::MAX)
+        };
+        within(counts[0], self.min_digit, self.max_digit)
+            && within(counts[1], self.min_lower, self.max_lower)
+            && within(counts[2], self.min_upper, self.max_upper)
+            && within(counts[3], self.min_special, self.max_special)
+    }
+}
+
+pub fn policygen(
+    output: Option<PathBuf>,
+    policy: &Policy,
+    noncompliant: bool,
+    pps: u64,
+    show_masks: bool,
+) {
+    let mut writer = get_writer(output);
+
+    let mut total_masks: u64 = 0;
+    let mut written_masks: u64 = 0;
+    let mut total_keyspace: u128 = 0;
+    let mut written_keyspace: u128 = 0;
+
+    if show_masks {
+        eprintln!(
+            "[*] Masks:                                                keyspace      runtime"
+        );
+    }
+
+    for length in policy.min_length..=policy.max_length {
+        // every position holds an index into CLASSES, incremented like an odometer
+        let mut positions = vec![0usize; length];
+        loop {
+            let mut counts = [0usize; 4];
+            for class in &positions {
+                counts[*class] += 1;
+            }
+            let mask = Mask::from_classes(positions.iter().map(|class| CLASSES[*class]));
+            let keyspace = mask.keyspace();
+
+            total_masks += 1;
+            total_keyspace = total_keyspace.saturating_add(keyspace);
+
+            if policy.complies(&counts) != noncompliant {
+                written_masks += 1;
+                written_keyspace = written_keyspace.saturating_add(keyspace);
+                if show_masks {
+                    eprintln!(
+                        "[+] {: >40}: {: >20} {: >12}",
+                        mask,
+                        keyspace,
+                        format_runtime(keyspace / pps as u128)
+                    );
+                }
+                let mut out = mask.to_bytes();
+                out.push(b'\n');
+                mywrite(&mut out.as_slice(), &mut writer);
+            }
+
+            let mut idx = 0;
+            while idx < length {
+                positions[idx] += 1;
+                if positions[idx] < CLASSES.len() {
+                    break;
+                }
+                positions[idx] = 0;
+                idx += 1;
+            }
+            if idx == length {
+                break;
+            }
+        }
+    }
+
+    let compliance = match noncompliant {
+        false => "compliant",
+        true => "non-compliant",
+    };
+    eprintln!(
+        "[*] Generated {} {} masks out of {} total.",
+        written_masks, compliance, total_masks
+    );
+    eprintln!(
+        "[*] Keyspace: {} of {} total.",
+        written_keyspace, total_keyspace
+    );
+    eprintln!(
+        "[*] Runtime: {} at {} h/s ({} for all masks)",
+        format_runtime(written_keyspace / pps as u128),
+        pps,
+        format_runtime(total_keyspace / pps as u128)
+    );
+}
@@ -1,210 +1,1160 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::iter::FromIterator;
-use std::path::PathBuf;
 
-use bstr::{io::BufReadExt, ByteSlice};
+use bstr::ByteSlice;
 use hashbrown::HashMap;
+use pack2_util::spacesaving::{Counter, SpaceSaving};
+use pack2_util::utf8;
 use pack2_util::*;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-const DEFAULT_ENCODED: u64 = 0x00000000ffff0000;
+#[derive(Debug, Serialize)]
+pub struct LengthEntry {
+    pub length: usize,
+    pub count: u64,
+    pub percent: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CharsetEntry {
+    pub charset: &'static str,
+    pub count: u64,
+    pub percent: f64,
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+/// Summary of the length distribution, percentiles use the nearest rank
+#[derive(Debug, Serialize)]
+pub struct LengthSummary {
+    pub mean: f64,
+    pub stddev: f64,
+    pub median: usize,
+    pub p90: usize,
+    pub p99: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClassCountEntry {
+    /// Number of characters of the class
+    pub characters: usize,
+    pub count: u64,
+    pub percent: f64,
+}
+
+/// How many passwords contain exactly n characters of a class
+#[derive(Debug, Serialize)]
+pub struct ClassDistribution {
+    pub class: &'static str,
+    pub counts: Vec<ClassCountEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimpleMaskEntry {
+    pub simple_mask: String,
+    pub count: u64,
+    pub percent: f64,
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaskEntry {
+    pub mask: String,
+    pub count: u64,
+    pub percent: f64,
+    pub keyspace: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub efficiency: Option<f64>,
+    /// With --max-memory the count may be overestimated by up to `error`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<u64>,
+}
+
+/// Result of `Stats::report`, all sections are sorted by count
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub total: u64,
+    pub analyzed: u64,
+    pub skipped: u64,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub length_summary: LengthSummary,
+    pub lengths: Vec<LengthEntry>,
+    pub class_counts: Vec<ClassDistribution>,
+    pub charsets: Vec<CharsetEntry>,
+    pub simple_masks: Vec<SimpleMaskEntry>,
+    /// Full simple masks of the passwords counted as othermask
+    pub long_simple_masks: Vec<SimpleMaskEntry>,
+    /// With --max-memory the number of passwords counted as othermask whose
+    /// full simple mask was not kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlisted_long_simple_masks: Option<u64>,
+    /// Number of masks before --top, --min-count, --min-percent and --max-keyspace,
+    /// with --max-memory only the masks still tracked at the end
+    pub distinct_masks: usize,
+    /// With --max-memory masks not listed occurred at most this many times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_unlisted_count: Option<u64>,
+    pub masks: Vec<MaskEntry>,
+}
+
+/// Raw counters written with --snapshot, several snapshots can be combined by statsmerge
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    utf8: bool,
+    processed_lines: u64,
+    skipped_lines: u64,
+    min_length: usize,
+    max_length: usize,
+    /// length, count
+    lengths: Vec<(usize, u64)>,
+    /// charset bitmap, count, min length, max length
+    charsets: Vec<(u8, u64, usize, usize)>,
+    /// simple mask, count, min length, max length
+    simple_masks: Vec<(Vec<u8>, u64, usize, usize)>,
+    // the fields added later default to empty so that older snapshots fail the version check
+    /// class, number of characters of the class, count
+    #[serde(default)]
+    class_counts: Vec<(char, usize, u64)>,
+    #[serde(default)]
+    simple_mask_options: SimpleMaskOptions,
+    /// simple mask past the segment cap, count, min length, max length
+    #[serde(default)]
+    long_simple_masks: Vec<(Vec<u8>, u64, usize, usize)>,
+    /// Passwords whose simple mask past the cap was dropped to stay within the capacity
+    #[serde(default)]
+    unlisted_long_simple_masks: u64,
+    /// Capacity and upper bound of the masks not listed if counted approximately
+    approximate: Option<(usize, u64)>,
+    /// mask, count, error
+    masks: Vec<(String, u64, u64)>,
+}
+
+/// Bumped whenever a field is added, older snapshots lack counters and are rejected
+const SNAPSHOT_VERSION: u32 = 4;
+
+/// The classes of `Stats::class_counts` by their mask character
+const CLASSES: [(u8, &str); 5] = [
+    (b'l', "lower"),
+    (b'u', "upper"),
+    (b'd', "digit"),
+    (b's', "special"),
+    (b'b', "binary"),
+];
 
 #[inline(always)]
-fn encode_count_min_max(count: u32, min: u16, max: u16) -> u64 {
-    let encoded: u64 = (count as u64) << 32 | (min as u64) << 16 | (max as u64);
-    encoded
+fn class_index(class: u8) -> usize {
+    match class {
+        b'l' => 0,
+        b'u' => 1,
+        b'd' => 2,
+        b's' => 3,
+        _ => 4,
+    }
+}
+
+/// Number of characters of every class in `CLASSES`, like the hashcat charsets
+const CLASS_SIZES: [u128; 5] = [26, 26, 10, 33, 256];
+
+/// Keyspace of a mask of classes without building the charsets of a `Mask`
+fn mask_keyspace(mask: &[u8]) -> u128 {
+    mask.iter().fold(1u128, |keyspace, class| {
+        keyspace.saturating_mul(CLASS_SIZES[class_index(*class)])
+    })
 }
 
+/// Segments of a simple mask, strings are only split into lower, upper and
+/// mixed case with `SimpleMaskOptions::case`
+const SIMPLE_STRING: u8 = 1;
+const SIMPLE_UPPER: u8 = 2;
+const SIMPLE_MIXED: u8 = 3;
+const SIMPLE_DIGIT: u8 = 4;
+const SIMPLE_SPECIAL: u8 = 8;
+const SIMPLE_BINARY: u8 = 16;
+const SIMPLE_LOWER: u8 = 32;
+/// Stands for every password with more segments than `SimpleMaskOptions::max_segments`
+const SIMPLE_OTHERMASK: u8 = 255;
+
+fn simple_mask_name(segment: u8) -> &'static str {
+    match segment {
+        SIMPLE_STRING => "string",
+        SIMPLE_UPPER => "upper",
+        SIMPLE_MIXED => "mixed",
+        SIMPLE_DIGIT => "digit",
+        SIMPLE_SPECIAL => "special",
+        SIMPLE_BINARY => "binary",
+        SIMPLE_LOWER => "lower",
+        _ => "othermask",
+    }
+}
+
+/// How passwords are reduced to simple masks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimpleMaskOptions {
+    /// Passwords with more segments are counted as othermask
+    pub max_segments: usize,
+    /// Distinguish lower, upper and mixed case strings
+    pub case: bool,
+    /// Count binary characters as special
+    pub fold_binary: bool,
+}
+
+impl Default for SimpleMaskOptions {
+    fn default() -> SimpleMaskOptions {
+        SimpleMaskOptions {
+            max_segments: 4,
+            case: false,
+            fold_binary: false,
+        }
+    }
+}
+
+/// Builds the simple mask of a password from its mask, one segment per run of
+/// characters of the same kind
 #[inline(always)]
-fn decode_count_min_max(encoded: u64) -> (u32, u16, u16) {
-    let count: u32 = (encoded >> 32) as u32;
-    let min: u16 = ((encoded >> 16) & 0xffff) as u16;
-    let max: u16 = (encoded & 0xffff) as u16;
-    (count, min, max)
+fn build_simple_mask(mask: &[u8], options: SimpleMaskOptions, simple_mask: &mut Vec<u8>) {
+    let is_string = |segment: u8| matches!(segment, SIMPLE_LOWER | SIMPLE_UPPER | SIMPLE_MIXED);
+    for class in mask {
+        let segment = match *class {
+            b'l' if options.case => SIMPLE_LOWER,
+            b'u' if options.case => SIMPLE_UPPER,
+            b'l' | b'u' => SIMPLE_STRING,
+            b'd' => SIMPLE_DIGIT,
+            b'b' if !options.fold_binary => SIMPLE_BINARY,
+            _ => SIMPLE_SPECIAL,
+        };
+        match simple_mask.last_mut() {
+            Some(last) if *last == segment => (),
+            Some(last) if is_string(*last) && is_string(segment) => *last = SIMPLE_MIXED,
+            _ => simple_mask.push(segment),
+        }
+    }
 }
 
-pub fn gen(
-    input: Option<PathBuf>,
-    output: Option<PathBuf>,
-    separator: Option<char>,
-    min_length: u16,
-    max_length: u16,
-) {
-    let bitmap2string = get_bitmap2string();
-    let reader = get_reader(input);
+/// Rough memory usage of one approximate mask counter including the mask itself,
+/// the hash map and the heap entry
+const BYTES_PER_MASK_COUNTER: usize = 160;
 
-    let mut masks: HashMap<Vec<u8>, u32> = HashMap::new();
-    let mut simple_masks: HashMap<Vec<u8>, u64> = HashMap::new();
-    let mut length: HashMap<u16, u32> = HashMap::new();
-    let mut charsets: HashMap<u8, u64> = HashMap::new();
+/// With a capacity, the share of counters used for the long simple masks
+const LONG_SIMPLE_MASK_SHARE: usize = 8;
 
-    let mut processed_lines: usize = 0;
-    let mut skipped_lined: usize = 0;
-    let mut min_len: usize = usize::MAX;
-    let mut max_len: usize = 0;
+/// Splits the counters of a capacity into the ones for the masks and the ones
+/// for the long simple masks
+fn split_capacity(capacity: usize) -> (usize, usize) {
+    let long_simple_masks = (capacity / LONG_SIMPLE_MASK_SHARE).max(1);
+    (
+        capacity.saturating_sub(long_simple_masks).max(1),
+        long_simple_masks,
+    )
+}
+
+/// Number of passwords together with the shortest and longest of them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct CountMinMax {
+    count: u64,
+    min: usize,
+    max: usize,
+}
 
-    let mut mask: Vec<u8> = Vec::new();
-    let mut simple_mask: Vec<u8> = Vec::new();
+impl Default for CountMinMax {
+    fn default() -> CountMinMax {
+        CountMinMax {
+            count: 0,
+            min: usize::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl CountMinMax {
+    #[inline(always)]
+    fn update(&mut self, line_len: usize) {
+        self.count += 1;
+        self.min = self.min.min(line_len);
+        self.max = self.max.max(line_len);
+    }
+
+    fn merge(&mut self, other: CountMinMax) {
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
 
-    for result in reader.byte_lines() {
-        let (line, line_len) = decode_hex_if_needed(result.unwrap());
+/// Sorts the entries passing `keep` by count, descending. Ties are ordered by key so the
+/// output doesn't depend on the hash map iteration order or the number of threads.
+/// With a `limit` only the top entries are kept in a heap instead of sorting all of them.
+fn sort_by_count<I, K, V, F>(entries: I, limit: Option<usize>, keep: F) -> Vec<(K, V)>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Ord,
+    V: Ord,
+    F: Fn(&K, &V) -> bool,
+{
+    let entries = entries.into_iter().filter(|(key, value)| keep(key, value));
 
-        if line_len < min_length.into() || line_len > max_length.into() {
-            skipped_lined += 1;
-            continue;
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            let mut freq = Vec::from_iter(entries);
+            freq.sort_unstable_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+            return freq;
         }
+    };
 
-        if min_len > line_len {
-            min_len = line_len
+    // min-heap of the best entries so far, the worst one is replaced first
+    let mut heap = BinaryHeap::with_capacity(limit + 1);
+    for (key, value) in entries {
+        heap.push(Reverse((value, Reverse(key))));
+        if heap.len() > limit {
+            heap.pop();
         }
-        if line_len > max_len {
-            max_len = line_len
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((value, Reverse(key)))| (key, value))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Add the efficiency (count / keyspace) to every mask of the report
+    pub efficiency: bool,
+    /// Classify and count code points instead of bytes
+    pub utf8: bool,
+    pub simple_mask: SimpleMaskOptions,
+    /// Number of entries kept per section
+    pub top: Option<usize>,
+    pub min_count: u64,
+    pub min_percent: f64,
+    /// Masks with a larger keyspace are dropped
+    pub max_keyspace: Option<u128>,
+    /// Count masks approximately using about this many bytes for every `Stats`
+    pub max_memory: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            min_length: 1,
+            max_length: usize::MAX,
+            efficiency: false,
+            utf8: false,
+            simple_mask: SimpleMaskOptions::default(),
+            top: None,
+            min_count: 0,
+            min_percent: 0.0,
+            max_keyspace: None,
+            max_memory: None,
         }
+    }
+}
 
-        let mut last: u8 = 0;
+impl Options {
+    /// Number of approximate counters of every `Stats`
+    fn mask_capacity(&self) -> Option<usize> {
+        self.max_memory.map(|bytes| bytes / BYTES_PER_MASK_COUNTER)
+    }
+}
 
-        let mut charset: u8 = 0;
-        let mut skip = false;
+/// The exact count of every mask or, to bound the memory usage, approximate
+/// counts of the most frequent ones
+enum MaskCounts {
+    Exact(HashMap<Vec<u8>, u64>),
+    Approximate(SpaceSaving<Vec<u8>>),
+}
 
-        for byte in line.iter() {
-            mask.push(CHAR2MASK[*byte as usize]);
-            charset |= CHAR2BITMAP[*byte as usize];
-            let char_mapped = CHAR2SMASK[*byte as usize];
-            if last == 0 || (last != char_mapped && !skip) {
-                simple_mask.push(char_mapped);
-                last = char_mapped;
+impl MaskCounts {
+    fn new(capacity: Option<usize>) -> MaskCounts {
+        match capacity {
+            None => MaskCounts::Exact(HashMap::new()),
+            Some(capacity) => MaskCounts::Approximate(SpaceSaving::new(capacity)),
+        }
+    }
+
+    #[inline(always)]
+    fn add(&mut self, mask: &[u8]) {
+        match self {
+            MaskCounts::Exact(masks) => match masks.get_mut(mask) {
+                Some(count) => *count += 1,
+                None => {
+                    masks.insert(mask.to_vec(), 1);
+                }
+            },
+            MaskCounts::Approximate(masks) => masks.add(mask),
+        }
+    }
+
+    /// Merging exact and approximate counts results in approximate counts
+    fn merge(&mut self, other: MaskCounts) {
+        let this = std::mem::replace(self, MaskCounts::Exact(HashMap::new()));
+        *self = match (this, other) {
+            (MaskCounts::Exact(mut masks), MaskCounts::Exact(other)) => {
+                for (mask, count) in other {
+                    *masks.entry(mask).or_insert(0) += count;
+                }
+                MaskCounts::Exact(masks)
             }
-            if simple_mask.len() > 4 {
-                skip = true;
-                simple_mask.clear();
-                simple_mask.push(255);
+            (MaskCounts::Approximate(mut masks), other)
+            | (other, MaskCounts::Approximate(mut masks)) => {
+                masks.merge(other.into_approximate(masks.capacity()));
+                MaskCounts::Approximate(masks)
             }
+        };
+    }
+
+    fn into_approximate(self, capacity: usize) -> SpaceSaving<Vec<u8>> {
+        match self {
+            MaskCounts::Approximate(masks) => masks,
+            exact => SpaceSaving::from_counters(capacity, 0, exact.into_counters()),
         }
+    }
 
-        *masks.entry((&*mask).to_vec()).or_insert(0) += 1;
-        *length.entry(line_len as u16).or_insert(0) += 1;
-        let charsets_entry = charsets.entry(charset).or_insert(DEFAULT_ENCODED);
-        let (mut charsets_count, mut charsets_min_len, mut charsets_max_len) =
-            decode_count_min_max(*charsets_entry);
+    fn len(&self) -> usize {
+        match self {
+            MaskCounts::Exact(masks) => masks.len(),
+            MaskCounts::Approximate(masks) => masks.len(),
+        }
+    }
 
-        charsets_count += 1;
-        if charsets_min_len as usize > line_len {
-            charsets_min_len = line_len as u16
+    /// Upper bound of the count of masks not included, `None` if counted exactly
+    fn max_unlisted_count(&self) -> Option<u64> {
+        match self {
+            MaskCounts::Exact(_) => None,
+            MaskCounts::Approximate(masks) => Some(masks.min_count()),
         }
-        if line_len > charsets_max_len as usize {
-            charsets_max_len = line_len as u16
+    }
+
+    /// The counters of all masks, sorted by mask
+    fn to_vec(&self) -> Vec<(Vec<u8>, Counter)> {
+        let mut counters: Vec<(Vec<u8>, Counter)> = match self {
+            MaskCounts::Exact(masks) => masks
+                .iter()
+                .map(|(mask, count)| {
+                    let counter = Counter {
+                        count: *count,
+                        error: 0,
+                    };
+                    (mask.clone(), counter)
+                })
+                .collect(),
+            MaskCounts::Approximate(masks) => masks
+                .iter()
+                .map(|(mask, counter)| (mask.clone(), *counter))
+                .collect(),
+        };
+        counters.sort_unstable();
+        counters
+    }
+
+    fn into_counters(self) -> Box<dyn Iterator<Item = (Vec<u8>, Counter)>> {
+        match self {
+            MaskCounts::Exact(masks) => Box::new(masks.into_iter().map(|(mask, count)| {
+                let counter = Counter { count, error: 0 };
+                (mask, counter)
+            })),
+            MaskCounts::Approximate(masks) => Box::new(masks.into_counters()),
         }
+    }
+}
+
+/// Builds the mask from the (mask, bitmap) class of every character and
+/// returns the charset of the whole line
+#[inline(always)]
+fn classify<I: Iterator<Item = (u8, u8)>>(classes: I, mask: &mut Vec<u8>) -> u8 {
+    let mut charset: u8 = 0;
+    for (mask_class, bitmap) in classes {
+        mask.push(mask_class);
+        charset |= bitmap;
+    }
+    charset
+}
+
+/// Counters of a (partial) statsgen run, the stats of several chunks can be merged
+pub struct Stats {
+    masks: MaskCounts,
+    simple_masks: HashMap<Vec<u8>, CountMinMax>,
+    /// Full simple masks of the passwords counted as othermask, with a capacity only
+    /// the most frequent ones are kept and the others counted as unlisted
+    long_simple_masks: HashMap<Vec<u8>, CountMinMax>,
+    long_simple_mask_capacity: Option<usize>,
+    unlisted_long_simple_masks: u64,
+    length: HashMap<usize, u64>,
+    charsets: HashMap<u8, CountMinMax>,
+    /// Per class, the number of passwords by how many characters of that class they contain
+    class_counts: [HashMap<usize, u64>; 5],
+
+    processed_lines: u64,
+    skipped_lines: u64,
+    min_len: usize,
+    max_len: usize,
 
-        *charsets_entry = encode_count_min_max(charsets_count, charsets_min_len, charsets_max_len);
+    min_length: usize,
+    max_length: usize,
+    utf8: bool,
+    simple_mask_options: SimpleMaskOptions,
 
-        let simple_mask_entry = simple_masks
-            .entry((&*simple_mask).to_vec())
-            .or_insert(DEFAULT_ENCODED);
-        let (mut simple_count, mut simple_min_len, mut simple_max_len) =
-            decode_count_min_max(*simple_mask_entry);
+    mask: Vec<u8>,
+    simple_mask: Vec<u8>,
+    hex_buf: Vec<u8>,
+}
 
-        simple_count += 1;
-        if simple_min_len as usize > line_len {
-            simple_min_len = line_len as u16
+impl Stats {
+    /// With a `mask_capacity` masks are counted approximately using that many counters,
+    /// an eighth of them is used to keep the most frequent long simple masks
+    pub fn new(
+        min_length: usize,
+        max_length: usize,
+        utf8: bool,
+        simple_mask_options: SimpleMaskOptions,
+        mask_capacity: Option<usize>,
+    ) -> Stats {
+        let capacities = mask_capacity.map(split_capacity);
+        Stats {
+            masks: MaskCounts::new(capacities.map(|(masks, _)| masks)),
+            simple_masks: HashMap::new(),
+            long_simple_masks: HashMap::new(),
+            long_simple_mask_capacity: capacities.map(|(_, long_simple_masks)| long_simple_masks),
+            unlisted_long_simple_masks: 0,
+            length: HashMap::new(),
+            charsets: HashMap::new(),
+            class_counts: Default::default(),
+            processed_lines: 0,
+            skipped_lines: 0,
+            min_len: usize::MAX,
+            max_len: 0,
+            min_length,
+            max_length,
+            utf8,
+            simple_mask_options,
+            mask: Vec::new(),
+            simple_mask: Vec::new(),
+            hex_buf: Vec::new(),
         }
-        if line_len > simple_max_len as usize {
-            simple_max_len = line_len as u16
+    }
+
+    pub fn with_options(options: &Options) -> Stats {
+        Stats::new(
+            options.min_length,
+            options.max_length,
+            options.utf8,
+            options.simple_mask,
+            options.mask_capacity(),
+        )
+    }
+
+    pub fn add_chunk(&mut self, chunk: &[u8]) {
+        for line in ByteSlice::lines(chunk) {
+            self.add_line(line);
         }
+    }
 
-        *simple_mask_entry = encode_count_min_max(simple_count, simple_min_len, simple_max_len);
+    pub fn add_line(&mut self, line: &[u8]) {
+        let line = decode_hex_line(line, &mut self.hex_buf);
+        let line_len = match self.utf8 {
+            false => line.len(),
+            true => utf8::char_count(line),
+        };
+
+        if line_len < self.min_length || line_len > self.max_length {
+            self.skipped_lines += 1;
+            return;
+        }
+
+        if self.min_len > line_len {
+            self.min_len = line_len
+        }
+        if line_len > self.max_len {
+            self.max_len = line_len
+        }
 
-        mask.clear();
-        simple_mask.clear();
-        processed_lines += 1;
+        let charset = match self.utf8 {
+            false => classify(
+                line.iter().map(|byte| {
+                    let idx = *byte as usize;
+                    (CHAR2MASK[idx], CHAR2BITMAP[idx])
+                }),
+                &mut self.mask,
+            ),
+            true => classify(
+                utf8::chars(line).map(|(_, c)| (utf8::char2mask(c), utf8::char2bitmap(c))),
+                &mut self.mask,
+            ),
+        };
+
+        self.masks.add(&self.mask);
+        *self.length.entry(line_len).or_insert(0) += 1;
+
+        let mut class_counts = [0usize; CLASSES.len()];
+        for class in &self.mask {
+            class_counts[class_index(*class)] += 1;
+        }
+        for (counts, n) in self.class_counts.iter_mut().zip(class_counts.iter()) {
+            *counts.entry(*n).or_insert(0) += 1;
+        }
+
+        self.charsets.entry(charset).or_default().update(line_len);
+        build_simple_mask(&self.mask, self.simple_mask_options, &mut self.simple_mask);
+        if self.simple_mask.len() > self.simple_mask_options.max_segments {
+            self.add_long_simple_mask(line_len);
+            self.simple_mask.clear();
+            self.simple_mask.push(SIMPLE_OTHERMASK);
+        }
+        self.simple_masks
+            .entry(self.simple_mask.to_vec())
+            .or_default()
+            .update(line_len);
+
+        self.mask.clear();
+        self.simple_mask.clear();
+        self.processed_lines += 1;
     }
 
-    let total_lines = processed_lines + skipped_lined;
-    eprintln!(
-        "[+] Analyzed {} / {} passwords.",
-        processed_lines, total_lines
-    );
+    /// Once the capacity is reached only the long simple masks already kept are counted
+    fn add_long_simple_mask(&mut self, line_len: usize) {
+        if let Some(entry) = self.long_simple_masks.get_mut(&self.simple_mask) {
+            entry.update(line_len);
+            return;
+        }
+        match self.long_simple_mask_capacity {
+            Some(capacity) if self.long_simple_masks.len() >= capacity => {
+                self.unlisted_long_simple_masks += 1;
+            }
+            _ => {
+                let mut entry = CountMinMax::default();
+                entry.update(line_len);
+                self.long_simple_masks
+                    .insert(self.simple_mask.to_vec(), entry);
+            }
+        }
+    }
 
-    let mut freq_len = Vec::from_iter(length);
-    freq_len.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
+    /// Drops all but the most frequent long simple masks if there are more than the capacity
+    fn truncate_long_simple_masks(&mut self) {
+        let capacity = match self.long_simple_mask_capacity {
+            Some(capacity) if self.long_simple_masks.len() > capacity => capacity,
+            _ => return,
+        };
+        let mut entries: Vec<(Vec<u8>, CountMinMax)> = self.long_simple_masks.drain().collect();
+        // ties are ordered by simple mask to stay deterministic
+        entries.sort_unstable_by(|(a_simple_mask, a), (b_simple_mask, b)| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a_simple_mask.cmp(b_simple_mask))
+        });
+        for (_, dropped) in entries.drain(capacity..) {
+            self.unlisted_long_simple_masks += dropped.count;
+        }
+        self.long_simple_masks = entries.into_iter().collect();
+    }
 
-    eprintln!(
-        "[*] Length distribution: (min: {} max: {})",
-        min_len, max_len
-    );
-    for (len, count) in freq_len {
-        let percent = 100.0 / processed_lines as f64 * count as f64;
-        eprintln!("[+] {: >26}: {: >6.2}% ({})", len, percent, count);
+    pub fn merge(&mut self, other: Stats) {
+        self.masks.merge(other.masks);
+        for (simple_mask, other_entry) in other.simple_masks {
+            self.simple_masks
+                .entry(simple_mask)
+                .or_default()
+                .merge(other_entry);
+        }
+        for (simple_mask, other_entry) in other.long_simple_masks {
+            self.long_simple_masks
+                .entry(simple_mask)
+                .or_default()
+                .merge(other_entry);
+        }
+        self.unlisted_long_simple_masks += other.unlisted_long_simple_masks;
+        self.truncate_long_simple_masks();
+        for (length, count) in other.length {
+            *self.length.entry(length).or_insert(0) += count;
+        }
+        for (charset, other_entry) in other.charsets {
+            self.charsets.entry(charset).or_default().merge(other_entry);
+        }
+        for (counts, other_counts) in self.class_counts.iter_mut().zip(other.class_counts) {
+            for (n, count) in other_counts {
+                *counts.entry(n).or_insert(0) += count;
+            }
+        }
+        self.processed_lines += other.processed_lines;
+        self.skipped_lines += other.skipped_lines;
+        self.min_len = self.min_len.min(other.min_len);
+        self.max_len = self.max_len.max(other.max_len);
     }
 
-    let mut freq_charsets = Vec::from_iter(charsets);
-    freq_charsets.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
+    /// Number of lines added, including the skipped ones
+    pub fn lines(&self) -> u64 {
+        self.processed_lines + self.skipped_lines
+    }
 
-    eprintln!("\n[*] Charset distribution:                    count   min   max");
-    for (charset, encoded) in freq_charsets {
-        let (count, min_len, max_len) = decode_count_min_max(encoded);
-        let percent = 100.0 / processed_lines as f64 * count as f64;
-        eprintln!(
-            "[+] {: >26}: {: >6.2}% {: >10} {: >5} {: >5}",
-            bitmap2string[charset as usize], percent, count, min_len, max_len
-        );
+    pub fn utf8(&self) -> bool {
+        self.utf8
     }
 
-    let mut freq_simple_masks = Vec::from_iter(simple_masks);
-    freq_simple_masks.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
-
-    eprintln!("\n[*] Simple masks distribution:               count   min   max");
-    for (simple_mask, encoded) in freq_simple_masks {
-        let (count, min_len, max_len) = decode_count_min_max(encoded);
-        let percent = 100.0 / processed_lines as f64 * count as f64;
-        let mut print_simple_mask: Vec<&str> = Vec::new();
-        for mapped_mask in simple_mask {
-            match mapped_mask {
-                1 => print_simple_mask.push("string"),
-                4 => print_simple_mask.push("digit"),
-                8 => print_simple_mask.push("special"),
-                16 => print_simple_mask.push("binary"),
-                255 => print_simple_mask.push("othermask"),
-                _ => (),
+    pub fn simple_mask_options(&self) -> SimpleMaskOptions {
+        self.simple_mask_options
+    }
+
+    /// Counts the masks approximately and limits the long simple masks within the
+    /// memory budget of `options`, if any, e.g. before merging stats read from snapshots
+    pub fn approximate_masks(&mut self, options: &Options) {
+        if let Some(capacity) = options.mask_capacity() {
+            let (mask_capacity, long_simple_mask_capacity) = split_capacity(capacity);
+            let masks = std::mem::replace(&mut self.masks, MaskCounts::new(None));
+            self.masks = MaskCounts::Approximate(masks.into_approximate(mask_capacity));
+            self.long_simple_mask_capacity = Some(long_simple_mask_capacity);
+            self.truncate_long_simple_masks();
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let mut lengths: Vec<(usize, u64)> = self.length.iter().map(|(l, c)| (*l, *c)).collect();
+        lengths.sort_unstable();
+
+        let mut charsets: Vec<(u8, u64, usize, usize)> = self
+            .charsets
+            .iter()
+            .map(|(charset, entry)| (*charset, entry.count, entry.min, entry.max))
+            .collect();
+        charsets.sort_unstable();
+
+        let simple_mask_entries = |simple_masks: &HashMap<Vec<u8>, CountMinMax>| {
+            let mut entries: Vec<(Vec<u8>, u64, usize, usize)> = simple_masks
+                .iter()
+                .map(|(simple_mask, entry)| {
+                    (simple_mask.clone(), entry.count, entry.min, entry.max)
+                })
+                .collect();
+            entries.sort_unstable();
+            entries
+        };
+        let simple_masks = simple_mask_entries(&self.simple_masks);
+        let long_simple_masks = simple_mask_entries(&self.long_simple_masks);
+
+        let mut class_counts: Vec<(char, usize, u64)> = Vec::new();
+        for ((class, _), counts) in CLASSES.iter().zip(self.class_counts.iter()) {
+            for (n, count) in counts {
+                class_counts.push((*class as char, *n, *count));
             }
         }
+        class_counts.sort_unstable();
+
+        let approximate = match &self.masks {
+            MaskCounts::Exact(_) => None,
+            MaskCounts::Approximate(masks) => Some((masks.capacity(), masks.min_count())),
+        };
+        let masks = self
+            .masks
+            .to_vec()
+            .into_iter()
+            .map(|(mask, counter)| {
+                let mask = String::from_utf8(mask).unwrap();
+                (mask, counter.count, counter.error)
+            })
+            .collect();
+
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            utf8: self.utf8,
+            processed_lines: self.processed_lines,
+            skipped_lines: self.skipped_lines,
+            min_length: self.min_len,
+            max_length: self.max_len,
+            lengths,
+            charsets,
+            simple_masks,
+            class_counts,
+            simple_mask_options: self.simple_mask_options,
+            long_simple_masks,
+            unlisted_long_simple_masks: self.unlisted_long_simple_masks,
+            approximate,
+            masks,
+        }
+    }
 
-        eprintln!(
-            "[+] {: >26}: {: >6.2}% {: >10} {: >5} {: >5}",
-            print_simple_mask.join(""),
-            percent,
-            count,
-            min_len,
-            max_len
+    fn from_snapshot(snapshot: Snapshot) -> Stats {
+        let mut stats = Stats::new(
+            1,
+            usize::MAX,
+            snapshot.utf8,
+            snapshot.simple_mask_options,
+            None,
         );
+        stats.processed_lines = snapshot.processed_lines;
+        stats.skipped_lines = snapshot.skipped_lines;
+        stats.min_len = snapshot.min_length;
+        stats.max_len = snapshot.max_length;
+        stats.length = snapshot.lengths.into_iter().collect();
+        stats.charsets = snapshot
+            .charsets
+            .into_iter()
+            .map(|(charset, count, min, max)| (charset, CountMinMax { count, min, max }))
+            .collect();
+        let simple_mask_entries = |entries: Vec<(Vec<u8>, u64, usize, usize)>| {
+            entries
+                .into_iter()
+                .map(|(simple_mask, count, min, max)| {
+                    (simple_mask, CountMinMax { count, min, max })
+                })
+                .collect()
+        };
+        stats.simple_masks = simple_mask_entries(snapshot.simple_masks);
+        stats.long_simple_masks = simple_mask_entries(snapshot.long_simple_masks);
+        stats.unlisted_long_simple_masks = snapshot.unlisted_long_simple_masks;
+        for (class, n, count) in snapshot.class_counts {
+            stats.class_counts[class_index(class as u8)].insert(n, count);
+        }
+
+        let masks = snapshot.masks.into_iter();
+        stats.masks = match snapshot.approximate {
+            None => MaskCounts::Exact(
+                masks
+                    .map(|(mask, count, _)| (mask.into_bytes(), count))
+                    .collect(),
+            ),
+            Some((capacity, min_count)) => {
+                let counters =
+                    masks.map(|(mask, count, error)| (mask.into_bytes(), Counter { count, error }));
+                MaskCounts::Approximate(SpaceSaving::from_counters(capacity, min_count, counters))
+            }
+        };
+        stats
+    }
+
+    pub fn report(self, options: &Options) -> Report {
+        let bitmap2string = get_bitmap2string();
+        let processed_lines = self.processed_lines;
+        let percent_of = |count: u64| 100.0 / processed_lines as f64 * count as f64;
+        let frequent =
+            |count: u64| count >= options.min_count && percent_of(count) >= options.min_percent;
+        let top = options.top;
+        let distinct_masks = self.masks.len();
+        let max_unlisted_count = self.masks.max_unlisted_count();
+        let approximate = max_unlisted_count.is_some();
+
+        let length_summary = length_summary(&self.length);
+        let lengths = sort_by_count(self.length, top, |_, count| frequent(*count))
+            .into_iter()
+            .map(|(length, count)| LengthEntry {
+                length,
+                count,
+                percent: percent_of(count),
+            })
+            .collect();
+
+        let class_counts = CLASSES
+            .iter()
+            .zip(self.class_counts)
+            .map(|((_, class), counts)| {
+                let mut counts = sort_by_count(counts, top, |_, count| frequent(*count));
+                counts.sort_unstable();
+                ClassDistribution {
+                    class,
+                    counts: counts
+                        .into_iter()
+                        .map(|(characters, count)| ClassCountEntry {
+                            characters,
+                            count,
+                            percent: percent_of(count),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let charsets = sort_by_count(self.charsets, top, |_, entry| frequent(entry.count))
+            .into_iter()
+            .map(|(charset, entry)| CharsetEntry {
+                charset: bitmap2string[charset as usize],
+                count: entry.count,
+                percent: percent_of(entry.count),
+                min_length: entry.min,
+                max_length: entry.max,
+            })
+            .collect();
+
+        let simple_mask_entries = |simple_masks: HashMap<Vec<u8>, CountMinMax>| {
+            sort_by_count(simple_masks, top, |_, entry| frequent(entry.count))
+                .into_iter()
+                .map(|(simple_mask, entry)| SimpleMaskEntry {
+                    simple_mask: simple_mask.into_iter().map(simple_mask_name).collect(),
+                    count: entry.count,
+                    percent: percent_of(entry.count),
+                    min_length: entry.min,
+                    max_length: entry.max,
+                })
+                .collect()
+        };
+        let simple_masks = simple_mask_entries(self.simple_masks);
+        let long_simple_masks = simple_mask_entries(self.long_simple_masks);
+        let unlisted_long_simple_masks = match self.unlisted_long_simple_masks {
+            0 => None,
+            unlisted => Some(unlisted),
+        };
+
+        let keep_mask = |mask: &Vec<u8>, counter: &Counter| {
+            frequent(counter.count)
+                && match options.max_keyspace {
+                    None => true,
+                    Some(max_keyspace) => mask_keyspace(mask) <= max_keyspace,
+                }
+        };
+        let masks = sort_by_count(self.masks.into_counters(), top, keep_mask)
+            .into_iter()
+            .map(|(mask, Counter { count, error })| {
+                let keyspace = mask_keyspace(&mask);
+                MaskEntry {
+                    mask: mask
+                        .iter()
+                        .flat_map(|class| ['?', *class as char])
+                        .collect(),
+                    count,
+                    percent: percent_of(count),
+                    keyspace,
+                    // cracks per candidate, the higher the better
+                    efficiency: match options.efficiency {
+                        true => Some(count as f64 / keyspace as f64),
+                        false => None,
+                    },
+                    error: match approximate {
+                        true => Some(error),
+                        false => None,
+                    },
+                }
+            })
+            .collect();
+
+        Report {
+            total: self.processed_lines + self.skipped_lines,
+            analyzed: self.processed_lines,
+            skipped: self.skipped_lines,
+            min_length: self.min_len.min(self.max_len),
+            max_length: self.max_len,
+            length_summary,
+            lengths,
+            class_counts,
+            charsets,
+            simple_masks,
+            long_simple_masks,
+            unlisted_long_simple_masks,
+            distinct_masks,
+            max_unlisted_count,
+            masks,
+        }
+    }
+}
+
+/// Stats are (de)serialized as snapshot of the raw counters, which can be merged later
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Stats {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Stats, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(D::Error::custom(format!(
+                "unsupported snapshot version {}, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+        Ok(Stats::from_snapshot(snapshot))
     }
-    let mut freq_masks = Vec::from_iter(masks);
-    freq_masks.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
+}
 
-    eprintln!("\n[*] Masks (top 25):");
-    let mut top = 0;
-    let mut print_mask = vec![b'?'; max_len * 2];
+/// Mean, standard deviation and percentiles of the length histogram
+fn length_summary(lengths: &HashMap<usize, u64>) -> LengthSummary {
+    let mut sorted: Vec<(usize, u64)> = lengths.iter().map(|(l, c)| (*l, *c)).collect();
+    sorted.sort_unstable();
 
-    let mut writer = get_writer(output);
+    let total: u64 = sorted.iter().map(|(_, count)| count).sum();
+    if total == 0 {
+        return LengthSummary {
+            mean: 0.0,
+            stddev: 0.0,
+            median: 0,
+            p90: 0,
+            p99: 0,
+        };
+    }
+    let mean = sorted
+        .iter()
+        .map(|(length, count)| *length as f64 * *count as f64)
+        .sum::<f64>()
+        / total as f64;
+    let variance = sorted
+        .iter()
+        .map(|(length, count)| (*length as f64 - mean).powi(2) * *count as f64)
+        .sum::<f64>()
+        / total as f64;
 
-    let separator: char = match separator {
-        None => '\t',
-        Some(sep) => sep,
+    // the shortest length covering at least `percent` of all passwords
+    let percentile = |percent: u128| {
+        let rank = (total as u128 * percent).div_ceil(100).max(1);
+        let mut seen: u128 = 0;
+        for (length, count) in &sorted {
+            seen += *count as u128;
+            if seen >= rank {
+                return *length;
+            }
+        }
+        0
     };
 
-    for (mask, count) in freq_masks {
-        let mut idx = 0;
-        for c in mask {
-            print_mask[idx * 2 + 1] = c;
-            idx += 1;
-        }
-        let out_mask = &print_mask[0..idx * 2].to_str().unwrap();
-        let percent = 100.0 / processed_lines as f64 * count as f64;
-        if top < 25 {
-            eprintln!("[+] {: >26}: {: >6.2}% ({})", out_mask, percent, count);
-            top += 1;
-        }
-        let out = &*format!(
-            "{}{}{:.4}{}{}\n",
-            out_mask, separator, percent, separator, count
+    LengthSummary {
+        mean,
+        stddev: variance.sqrt(),
+        median: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+    }
+}
+
+/// Analyzes all `lines` on the calling thread, `$HEX[]` encoded lines are decoded first.
+/// Use `Stats::report` to get the distributions and masks.
+pub fn analyze<'a, I>(lines: I, options: &Options) -> Stats
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut stats = Stats::with_options(options);
+    for line in lines {
+        stats.add_line(line);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_sizes_match_charsets() {
+        for (class, _) in &CLASSES {
+            let keyspace = pack2_util::mask::Mask::from_classes(vec![*class]).keyspace();
+            assert_eq!(mask_keyspace(&[*class]), keyspace);
+        }
+        assert_eq!(mask_keyspace(b"ludsb"), 26 * 26 * 10 * 33 * 256);
+        assert_eq!(mask_keyspace(&[b'b'; 20]), u128::MAX);
+    }
+
+    #[test]
+    fn long_simple_masks_within_capacity() {
+        let options = SimpleMaskOptions::default();
+        let mut stats = Stats::new(1, usize::MAX, false, options, Some(16));
+        let mut other = Stats::new(1, usize::MAX, false, options, Some(16));
+        let lines: [&[u8]; 5] = [b"a1!a1", b"1a!1a", b"!a1!a", b"a!1a!", b"1!a1!"];
+        for (i, line) in lines.iter().enumerate() {
+            for _ in 0..=i {
+                stats.add_line(line);
+                other.add_line(line);
+            }
+        }
+        assert_eq!(stats.long_simple_masks.len(), 2);
+        stats.merge(other);
+        assert_eq!(stats.long_simple_masks.len(), 2);
+
+        let report = stats.report(&Options::default());
+        let listed: u64 = report.long_simple_masks.iter().map(|e| e.count).sum();
+        assert_eq!(listed + report.unlisted_long_simple_masks.unwrap(), 30);
+        assert_eq!(report.simple_masks[0].count, 30);
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut line = vec![b'a'; 70_000];
+        line.extend_from_slice(b"12");
+        let mut stats = Stats::with_options(&Options::default());
+        stats.add_line(&line);
+
+        for entry in stats.charsets.values().chain(stats.simple_masks.values()) {
+            assert_eq!((entry.count, entry.min, entry.max), (1, 70_002, 70_002));
+        }
+        let report = stats.report(&Options::default());
+        assert_eq!((report.min_length, report.max_length), (70_002, 70_002));
+        assert_eq!(report.lengths[0].length, 70_002);
+        assert_eq!(report.masks.len(), 1);
+        assert_eq!(report.masks[0].mask.len(), 2 * 70_002);
+        assert!(report.masks[0].mask.ends_with("?l?d?d"));
+    }
+
+    /// Sets every counter of stats made of a single line to `count`
+    fn set_counts(stats: &mut Stats, count: u64) {
+        stats.processed_lines = count;
+        stats.length.values_mut().for_each(|c| *c = count);
+        for class_counts in stats.class_counts.iter_mut() {
+            class_counts.values_mut().for_each(|c| *c = count);
+        }
+        for entry in stats.charsets.values_mut() {
+            entry.count = count;
+        }
+        for entry in stats.simple_masks.values_mut() {
+            entry.count = count;
+        }
+        match &mut stats.masks {
+            MaskCounts::Exact(masks) => masks.values_mut().for_each(|c| *c = count),
+            MaskCounts::Approximate(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn counts_above_u32() {
+        let big = u32::MAX as u64 + 10;
+
+        let mut entry = CountMinMax {
+            count: big,
+            min: 3,
+            max: 3,
+        };
+        entry.merge(CountMinMax {
+            count: big,
+            min: 5,
+            max: 5,
+        });
+        assert_eq!((entry.count, entry.min, entry.max), (2 * big, 3, 5));
+
+        let options = Options::default();
+        let mut stats = analyze(vec![&b"abc1"[..]], &options);
+        set_counts(&mut stats, big);
+        let mut other = analyze(vec![&b"abc1"[..]], &options);
+        set_counts(&mut other, big + 1);
+        stats.merge(other);
+
+        let json = serde_json::to_string(&stats).unwrap();
+        let restored: Stats = serde_json::from_str(&json).unwrap();
+
+        for report in [stats.report(&options), restored.report(&options)] {
+            let expected = 2 * big + 1;
+            assert_eq!(report.analyzed, expected);
+            assert_eq!(report.total, expected);
+            assert_eq!(report.lengths[0].count, expected);
+            assert_eq!(report.charsets[0].count, expected);
+            assert_eq!(report.simple_masks[0].count, expected);
+            assert_eq!(report.masks[0].count, expected);
+            for class in &report.class_counts {
+                assert_eq!(class.counts[0].count, expected);
+            }
+            assert_eq!(report.length_summary.mean, 4.0);
+            assert_eq!(report.length_summary.median, 4);
+        }
+    }
+
+    #[test]
+    fn rejects_older_snapshots() {
+        let options = Options::default();
+        let stats = analyze(vec![&b"abc1"[..]], &options);
+        let json = serde_json::to_string(&stats).unwrap();
+        let old = json.replace(
+            &format!("\"version\":{}", SNAPSHOT_VERSION),
+            "\"version\":2",
         );
-        mywrite(&mut out.as_bytes(), &mut writer);
+        assert!(serde_json::from_str::<Stats>(&json).is_ok());
+        assert!(serde_json::from_str::<Stats>(&old).is_err());
     }
 }
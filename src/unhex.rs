@@ -1,15 +1,17 @@
 use std::path::PathBuf;
 
-use bstr::{io::BufReadExt, ByteSlice};
 use pack2_util::*;
 
-pub fn unhex(input: Option<PathBuf>, output: Option<PathBuf>) {
-    let reader = get_reader(input);
+pub fn unhex(input: Vec<PathBuf>, output: Option<PathBuf>) {
     let mut writer = get_writer(output);
+    let mut hex_buf: Vec<u8> = Vec::new();
+    let mut out: Vec<u8> = Vec::new();
 
-    for result in reader.byte_lines() {
-        let (mut line, _line_len) = decode_hex_if_needed(result.unwrap());
-        line.push(b'\n');
-        mywrite(&mut line.as_bytes(), &mut writer);
-    }
+    for_each_line(input, |line| {
+        let line = decode_hex_line(line, &mut hex_buf);
+        out.clear();
+        out.extend_from_slice(line);
+        out.push(b'\n');
+        mywrite(&mut out.as_slice(), &mut writer);
+    });
 }